@@ -0,0 +1,145 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// pitch and loudness of the rendered buzzer
+const TONE_FREQUENCY: f64 = 440.0;
+const TONE_VOLUME: f64 = 0.25;
+
+// size of the RIFF/WAVE header written in front of the samples
+const HEADER_SIZE: u32 = 44;
+
+// writes 16-bit mono PCM samples to a WAV file, the header sizes are
+// patched in when the writer is finished
+pub struct WavWriter
+{
+    out: BufWriter<File>,
+    sample_rate: u32,
+    samples: u32,
+}
+
+impl WavWriter
+{
+    pub fn create(filename: &str, sample_rate: u32) -> io::Result<WavWriter>
+    {
+        let mut writer = WavWriter
+        {
+            out: BufWriter::new(File::create(filename)?),
+            sample_rate,
+            samples: 0,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()>
+    {
+        let data_size = self.samples * 2;
+        let out = &mut self.out;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;               // chunk size
+        out.write_all(&1u16.to_le_bytes())?;                // PCM
+        out.write_all(&1u16.to_le_bytes())?;                // mono
+        out.write_all(&self.sample_rate.to_le_bytes())?;
+        out.write_all(&(self.sample_rate * 2).to_le_bytes())?; // byte rate
+        out.write_all(&2u16.to_le_bytes())?;                // block align
+        out.write_all(&16u16.to_le_bytes())?;               // bits per sample
+
+        out.write_all(b"data")?;
+        out.write_all(&data_size.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn write_sample(&mut self, sample: i16) -> io::Result<()>
+    {
+        self.out.write_all(&sample.to_le_bytes())?;
+        self.samples += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.out.flush()
+    }
+}
+
+// audio sink rendering the buzzer into a WAV file. it is driven with emulated
+// time rather than wall-clock time, so the output is identical no matter how
+// fast the host runs the VM (or whether an audio device exists at all)
+pub struct AudioCapture
+{
+    writer: WavWriter,
+    phase: f64,
+    pending: f64,
+}
+
+impl AudioCapture
+{
+    pub fn create(filename: &str) -> io::Result<AudioCapture>
+    {
+        Ok(AudioCapture
+        {
+            writer: WavWriter::create(filename, SAMPLE_RATE)?,
+            phase: 0.0,
+            pending: 0.0,
+        })
+    }
+
+    // renders `seconds` of emulated time with the buzzer either on or off.
+    // fractions of a sample are carried over to the next call
+    pub fn render(&mut self, active: bool, seconds: f64) -> io::Result<()>
+    {
+        self.pending += seconds * SAMPLE_RATE as f64;
+        let count = self.pending.floor();
+        self.pending -= count;
+
+        for _ in 0..count as u32
+        {
+            let sample = if active { self.tone() } else { 0 };
+            self.writer.write_sample(sample)?;
+        }
+
+        // restart the wave when silent so every beep starts the same way
+        if !active
+        {
+            self.phase = 0.0;
+        }
+        Ok(())
+    }
+
+    // next sample of a square wave
+    fn tone(&mut self) -> i16
+    {
+        let level = if self.phase < 0.5 { TONE_VOLUME } else { -TONE_VOLUME };
+        self.phase = (self.phase + TONE_FREQUENCY / SAMPLE_RATE as f64).fract();
+        (level * i16::MAX as f64) as i16
+    }
+
+    pub fn finish(self) -> io::Result<()>
+    {
+        self.writer.finish()
+    }
+}
@@ -0,0 +1,107 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::config::Config;
+use crate::gif;
+use crate::json::Json;
+use crate::octo;
+
+// Octo options and the quirks they stand for, inverted where Octo's meaning
+// is the opposite of ours
+const QUIRKS: [(&str, &str, bool); 5] =
+[
+    ("shiftQuirks", "shift", false),
+    ("loadStoreQuirks", "memory", true),
+    ("jumpQuirks", "jump", false),
+    ("logicQuirks", "vf_reset", false),
+    ("vBlankQuirks", "display_wait", false),
+];
+
+// Octo colours in the order of our palettes
+const COLORS: [&str; 4] = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+
+// a program shared as an Octo cartridge: a GIF whose frames carry a payload
+// in the low two bits of their colour indices, four pixels per byte with the
+// high bits first. the payload is a 32-bit big-endian length followed by
+// that many bytes of JSON:
+//
+//   { "program": "...", "options": { "tickrate": 20, "shiftQuirks": false, ... } }
+pub struct Cartridge
+{
+    pub program: Vec<u8>,
+    pub config: Config,
+}
+
+pub fn load(data: &[u8]) -> Result<Cartridge, String>
+{
+    let payload = payload(&gif::decode(data)?)?;
+    let text = String::from_utf8(payload).map_err(|_| "invalid cartridge payload".to_string())?;
+    let json = Json::parse(&text).map_err(|e| format!("cartridge payload: {}", e))?;
+
+    let program = match json.get("program")
+    {
+        Some(Json::String(source)) => octo::assemble(source).map_err(|e| format!("cartridge program: {}", e))?,
+        Some(Json::Array(bytes)) => bytes.iter().map(|b| b.as_f64().map(|b| b as u8)).collect::<Option<Vec<u8>>>()
+            .ok_or("invalid program bytes")?,
+        _ => return Err("the cartridge has no program".to_string()),
+    };
+
+    let mut config = Config::default();
+    if let Some(options) = json.get("options")
+    {
+        if let Some(tickrate) = options.get("tickrate").and_then(|t| t.as_f64())
+        {
+            config.set("emulation", "ipf", (tickrate as u32).to_string());
+        }
+
+        for &(option, quirk, invert) in QUIRKS.iter()
+        {
+            if let Some(value) = options.get(option).and_then(|v| v.as_bool())
+            {
+                config.set("quirks", quirk, (value != invert).to_string());
+            }
+        }
+
+        let colors: Vec<&str> = COLORS.iter().filter_map(|&c| options.get(c).and_then(|v| v.as_str())).collect();
+        if colors.len() == COLORS.len()
+        {
+            config.set("display", "palette", colors.join(","));
+        }
+
+        if let Some(rotation) = options.get("screenRotation").and_then(|r| r.as_f64())
+        {
+            config.set("display", "rotation", (rotation as u32).to_string());
+        }
+    }
+
+    Ok(Cartridge { program, config })
+}
+
+fn payload(frames: &[Vec<u8>]) -> Result<Vec<u8>, String>
+{
+    let bits: Vec<u8> = frames.iter().flat_map(|f| f.iter().map(|p| p & 3)).collect();
+    let mut bytes = bits.chunks_exact(4).map(|c| c[0] << 6 | c[1] << 4 | c[2] << 2 | c[3]);
+
+    let mut length = 0usize;
+    for _ in 0..4
+    {
+        length = length << 8 | bytes.next().ok_or("not an Octo cartridge")? as usize;
+    }
+
+    let payload: Vec<u8> = bytes.take(length).collect();
+    if payload.len() != length
+    {
+        return Err("not an Octo cartridge".to_string());
+    }
+    Ok(payload)
+}
@@ -0,0 +1,397 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+// RCA CDP1802, the CPU of the COSMAC VIP. CHIP-8 programs call its machine
+// code with 0NNN
+
+// what the CPU sees of the machine around it: memory, the I/O ports of INP
+// and OUT, the EF1-EF4 flag inputs and the Q output
+pub trait Bus
+{
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    fn input(&mut self, port: u8) -> u8;
+    fn output(&mut self, port: u8, value: u8);
+    fn flag(&mut self, number: u8) -> bool;
+    fn q(&mut self, _on: bool) {}
+}
+
+#[derive(Clone, Default)]
+pub struct Cdp1802
+{
+    pub r: [u16; 16], // scratchpad registers
+    pub p: u8,        // designates the program counter
+    pub x: u8,        // designates the data pointer
+    pub d: u8,
+    pub df: bool,
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    pub idle: bool,
+}
+
+impl Cdp1802
+{
+    // the state after a reset: everything runs off R0
+    pub fn new() -> Cdp1802
+    {
+        Cdp1802 { ie: true, ..Default::default() }
+    }
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> u8
+    {
+        let p = self.p as usize;
+        let value = bus.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    fn rx(&self) -> u16
+    {
+        self.r[self.x as usize]
+    }
+
+    // an interrupt saves X and P in T and continues with R1 as the program
+    // counter and R2 as the data pointer. returns the machine cycle it took
+    pub fn interrupt(&mut self) -> u32
+    {
+        if !self.ie
+        {
+            return 0;
+        }
+
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    // a DMA output cycle reads the byte at R0 for the peripheral
+    pub fn dma_out(&mut self, bus: &mut dyn Bus) -> u8
+    {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    // executes one instruction and returns the machine cycles it took, 2 or
+    // 3 for the long branches and skips. one machine cycle is 8 clocks
+    pub fn step(&mut self, bus: &mut dyn Bus) -> u32
+    {
+        if self.idle
+        {
+            return 1;
+        }
+
+        let opcode = self.fetch(bus);
+        let n = (opcode & 0x0F) as usize;
+
+        match opcode >> 4
+        {
+            0x0 if n == 0 => self.idle = true,                                  // IDL
+            0x0 => self.d = bus.read(self.r[n]),                                // LDN
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),                       // INC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),                       // DEC
+            0x3 => self.short_branch(n, bus),
+            0x4 =>                                                              // LDA
+            {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            0x5 => bus.write(self.r[n], self.d),                                // STR
+            0x6 if n == 0 => self.r[self.x as usize] = self.rx().wrapping_add(1), // IRX
+            0x6 if n < 8 =>                                                     // OUT
+            {
+                let value = bus.read(self.rx());
+                bus.output(n as u8, value);
+                self.r[self.x as usize] = self.rx().wrapping_add(1);
+            },
+            0x6 if n == 8 => {},                                                // 1804/1805 prefix, nothing on the 1802
+            0x6 =>                                                              // INP
+            {
+                self.d = bus.input(n as u8 - 8);
+                bus.write(self.rx(), self.d);
+            },
+            0x7 => self.control(n, bus),
+            0x8 => self.d = self.r[n] as u8,                                    // GLO
+            0x9 => self.d = (self.r[n] >> 8) as u8,                             // GHI
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,              // PLO
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,       // PHI
+            0xC =>
+            {
+                self.long_branch(n, bus);
+                return 3;
+            },
+            0xD => self.p = n as u8,                                            // SEP
+            0xE => self.x = n as u8,                                            // SEX
+            _ => self.alu(n, bus),
+        }
+        2
+    }
+
+    // BR, BQ, BZ, BDF, B1-B4, SKP and the negated forms. the target replaces
+    // the low byte of the program counter
+    fn short_branch(&mut self, n: usize, bus: &mut dyn Bus)
+    {
+        let condition = match n & 7
+        {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.flag(flag as u8 - 3),
+        };
+        let taken = if n == 8 { false } else { condition != (n >= 8) };
+
+        let p = self.p as usize;
+        if taken
+        {
+            let target = bus.read(self.r[p]);
+            self.r[p] = self.r[p] & 0xFF00 | target as u16;
+        }
+        else
+        {
+            self.r[p] = self.r[p].wrapping_add(1);
+        }
+    }
+
+    // LBR, LBQ, LBZ, LBDF, NOP, LSNQ, LSNZ, LSNF, LSKP, LBNQ, LBNZ, LBNF,
+    // LSIE, LSQ, LSZ, LSDF
+    fn long_branch(&mut self, n: usize, bus: &mut dyn Bus)
+    {
+        let condition = match n & 3
+        {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            _ => self.df,
+        };
+
+        let p = self.p as usize;
+        match n
+        {
+            0x4 => {},                                                          // NOP
+            0xC => if self.ie { self.r[p] = self.r[p].wrapping_add(2) },        // LSIE
+            0x5..=0x8 | 0xD..=0xF =>                                            // skips, LSKP skips always
+            {
+                if n == 0x8 || condition == (n >= 0xC)
+                {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            },
+            _ =>
+            {
+                if condition != (n >= 8)
+                {
+                    let high = bus.read(self.r[p]) as u16;
+                    let low = bus.read(self.r[p].wrapping_add(1)) as u16;
+                    self.r[p] = high << 8 | low;
+                }
+                else
+                {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            },
+        }
+    }
+
+    fn control(&mut self, n: usize, bus: &mut dyn Bus)
+    {
+        match n
+        {
+            0x0 | 0x1 =>                                                        // RET, DIS
+            {
+                let value = bus.read(self.rx());
+                self.r[self.x as usize] = self.rx().wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0x0F;
+                self.ie = n == 0;
+            },
+            0x2 =>                                                              // LDXA
+            {
+                self.d = bus.read(self.rx());
+                self.r[self.x as usize] = self.rx().wrapping_add(1);
+            },
+            0x3 =>                                                              // STXD
+            {
+                bus.write(self.rx(), self.d);
+                self.r[self.x as usize] = self.rx().wrapping_sub(1);
+            },
+            0x4 => { let m = bus.read(self.rx()); self.add(m, self.df) },      // ADC
+            0x5 => { let m = bus.read(self.rx()); self.subtract(m, self.d, self.df) }, // SDB
+            0x6 =>                                                              // SHRC
+            {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            },
+            0x7 => { let m = bus.read(self.rx()); self.subtract(self.d, m, self.df) }, // SMB
+            0x8 => bus.write(self.rx(), self.t),                                // SAV
+            0x9 =>                                                              // MARK
+            {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            },
+            0xA => { self.q = false; bus.q(false) },                            // REQ
+            0xB => { self.q = true; bus.q(true) },                              // SEQ
+            0xC => { let m = self.fetch(bus); self.add(m, self.df) },        // ADCI
+            0xD => { let m = self.fetch(bus); self.subtract(m, self.d, self.df) }, // SDBI
+            0xE =>                                                              // SHLC
+            {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            },
+            _ => { let m = self.fetch(bus); self.subtract(self.d, m, self.df) }, // SMBI
+        }
+    }
+
+    // F0-FF: LDX, OR, AND, XOR, ADD, SD, SHR, SM and their immediate forms
+    fn alu(&mut self, n: usize, bus: &mut dyn Bus)
+    {
+        let operand = match n
+        {
+            0x6 | 0xE => 0,
+            0x8..=0xF => self.fetch(bus),
+            _ => bus.read(self.rx()),
+        };
+
+        match n & 7
+        {
+            0 => self.d = operand,                                              // LDX, LDI
+            1 => self.d |= operand,                                             // OR, ORI
+            2 => self.d &= operand,                                             // AND, ANI
+            3 => self.d ^= operand,                                             // XOR, XRI
+            4 => self.add(operand, false),                                      // ADD, ADI
+            5 => self.subtract(operand, self.d, true),                          // SD, SDI
+            6 if n == 0x6 =>                                                    // SHR
+            {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            },
+            6 =>                                                                // SHL
+            {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            },
+            _ => self.subtract(self.d, operand, true),                          // SM, SMI
+        }
+    }
+
+    fn add(&mut self, value: u8, carry: bool)
+    {
+        let sum = self.d as u16 + value as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // DF is set when there is no borrow
+    fn subtract(&mut self, minuend: u8, subtrahend: u8, no_borrow: bool)
+    {
+        let difference = minuend as i16 - subtrahend as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    struct Memory(Vec<u8>);
+
+    impl Bus for Memory
+    {
+        fn read(&mut self, address: u16) -> u8 { self.0[address as usize] }
+        fn write(&mut self, address: u16, value: u8) { self.0[address as usize] = value }
+        fn input(&mut self, _port: u8) -> u8 { 0 }
+        fn output(&mut self, _port: u8, _value: u8) {}
+        fn flag(&mut self, _number: u8) -> bool { false }
+    }
+
+    // runs one instruction from address 0 with R1 as X, pointing at `m`, and
+    // returns the CPU and the machine cycles
+    fn execute(program: &[u8], d: u8, df: bool, m: u8) -> (Cdp1802, u32)
+    {
+        let mut memory = Memory(vec![0; 0x100]);
+        memory.0[..program.len()].copy_from_slice(program);
+        memory.0[0xFF] = m;
+
+        let mut cpu = Cdp1802::new();
+        cpu.r[1] = 0xFF;
+        cpu.x = 1;
+        cpu.d = d;
+        cpu.df = df;
+        let cycles = cpu.step(&mut memory);
+        (cpu, cycles)
+    }
+
+    #[test]
+    fn subtract_with_borrow()
+    {
+        // SDB: D = M - D - !DF, DF set when there is no borrow
+        let (cpu, _) = execute(&[0x75], 3, true, 5);
+        assert_eq!((cpu.d, cpu.df), (2, true));
+        let (cpu, _) = execute(&[0x75], 3, false, 5);
+        assert_eq!((cpu.d, cpu.df), (1, true));
+        let (cpu, _) = execute(&[0x75], 5, true, 3);
+        assert_eq!((cpu.d, cpu.df), (0xFE, false));
+
+        // SMB: D = D - M - !DF
+        let (cpu, _) = execute(&[0x77], 5, false, 3);
+        assert_eq!((cpu.d, cpu.df), (1, true));
+        let (cpu, _) = execute(&[0x77], 3, true, 3);
+        assert_eq!((cpu.d, cpu.df), (0, true));
+        let (cpu, _) = execute(&[0x77], 3, false, 3);
+        assert_eq!((cpu.d, cpu.df), (0xFF, false));
+
+        // the immediate forms take the byte after the opcode
+        let (cpu, _) = execute(&[0x7D, 0x10], 0x20, false, 0);
+        assert_eq!((cpu.d, cpu.df, cpu.r[0]), (0xEF, false, 2));
+        let (cpu, _) = execute(&[0x7F, 0x10], 0x20, false, 0);
+        assert_eq!((cpu.d, cpu.df, cpu.r[0]), (0x0F, true, 2));
+    }
+
+    #[test]
+    fn long_skips()
+    {
+        // LSKP
+        assert_eq!(execute(&[0xC8], 0, false, 0).0.r[0], 3);
+
+        // LSZ
+        let (cpu, cycles) = execute(&[0xCE], 0, false, 0);
+        assert_eq!((cpu.r[0], cycles), (3, 3));
+        assert_eq!(execute(&[0xCE], 1, false, 0).0.r[0], 1);
+
+        // LSNZ
+        let (cpu, cycles) = execute(&[0xC6], 1, false, 0);
+        assert_eq!((cpu.r[0], cycles), (3, 3));
+        assert_eq!(execute(&[0xC6], 0, false, 0).0.r[0], 1);
+
+        // LBR takes both bytes of the target
+        assert_eq!(execute(&[0xC0, 0x12, 0x34], 0, false, 0).0.r[0], 0x1234);
+    }
+
+    #[test]
+    fn short_instructions_take_two_cycles()
+    {
+        let (cpu, cycles) = execute(&[0xF8, 0x42], 0, false, 0);
+        assert_eq!((cpu.d, cycles), (0x42, 2));
+    }
+}
@@ -0,0 +1,191 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::romdb::Database;
+use crate::sha1;
+
+// default configuration file, looked up in the working directory
+pub const DEFAULT_CONFIG: &str = "dale8.cfg";
+
+// ini-style configuration:
+//
+//   # comment
+//   [section]
+//   name = value
+//
+// section and entry names are case-insensitive
+pub struct Section
+{
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct Config
+{
+    sections: Vec<Section>,
+}
+
+impl Config
+{
+    pub fn load(filename: &str) -> Result<Config, String>
+    {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    // loads the global configuration, then what the ROM database knows
+    // about `data`, the settings that came with the ROM (from a cartridge)
+    // and the optional per-ROM file (`<rom_file>.cfg`) on top
+    pub fn load_for_rom(filename: Option<&str>, rom: &str, data: &[u8], settings: Option<Config>) -> Result<Config, String>
+    {
+        let mut config = match filename
+        {
+            Some(filename) => Config::load(filename)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Config::load(DEFAULT_CONFIG)?,
+            None => Config::default(),
+        };
+
+        if let Some(entry) = Database::load(&config)?.lookup(&sha1::hex(data))
+        {
+            config.merge(entry.config());
+        }
+        if let Some(settings) = settings
+        {
+            config.merge(settings);
+        }
+
+        let rom_config = format!("{}.cfg", rom);
+        if Path::new(&rom_config).exists()
+        {
+            config.merge(Config::load(&rom_config)?);
+        }
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String>
+    {
+        let mut config = Config::default();
+        let mut current = None;
+
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';')
+            {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']')
+            {
+                let name = line[1..line.len() - 1].trim().to_lowercase();
+                current = Some(config.section_index(&name));
+            }
+            else if let Some(pos) = line.find('=')
+            {
+                let name = line[..pos].trim().to_lowercase();
+                let value = line[pos + 1..].trim().to_string();
+                match current
+                {
+                    Some(index) => config.sections[index].set(&name, value),
+                    None => return Err(format!("line {}: entry outside of a section", number + 1)),
+                }
+            }
+            else
+            {
+                return Err(format!("line {}: syntax error", number + 1));
+            }
+        }
+        Ok(config)
+    }
+
+    // entries of `other` replace the ones with the same name
+    pub fn merge(&mut self, other: Config)
+    {
+        for section in other.sections
+        {
+            let index = self.section_index(&section.name);
+            for (name, value) in section.entries
+            {
+                self.sections[index].set(&name, value);
+            }
+        }
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section>
+    {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn get(&self, section: &str, name: &str) -> Option<&str>
+    {
+        self.section(section).and_then(|s| s.get(name))
+    }
+
+    // parses an optional value, e.g. config.value::<u32>("display", "scale")
+    pub fn value<T: FromStr>(&self, section: &str, name: &str) -> Result<Option<T>, String>
+    {
+        match self.get(section, name)
+        {
+            Some(text) => text.parse().map(Some).map_err(|_| format!("[{}]: invalid {} {}", section, name, text)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&mut self, section: &str, name: &str, value: String)
+    {
+        let index = self.section_index(section);
+        self.sections[index].set(name, value);
+    }
+
+    // finds or creates a section
+    fn section_index(&mut self, name: &str) -> usize
+    {
+        match self.sections.iter().position(|s| s.name == name)
+        {
+            Some(index) => index,
+            None =>
+            {
+                self.sections.push(Section { name: name.to_string(), entries: Vec::new() });
+                self.sections.len() - 1
+            }
+        }
+    }
+}
+
+impl Section
+{
+    pub fn get(&self, name: &str) -> Option<&str>
+    {
+        self.entries.iter().find(|e| e.0 == name).map(|e| e.1.as_str())
+    }
+
+    fn set(&mut self, name: &str, value: String)
+    {
+        match self.entries.iter_mut().find(|e| e.0 == name)
+        {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((name.to_string(), value)),
+        }
+    }
+}
+
+// splits a comma-separated list value
+pub fn list(value: &str) -> Vec<&str>
+{
+    value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).collect()
+}
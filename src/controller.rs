@@ -0,0 +1,240 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::collections::HashMap;
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+
+use crate::config::Config;
+use crate::dale8::VM;
+use crate::keymap;
+
+// analog values closer to the center than this are ignored
+const DEFAULT_DEADZONE: i16 = 8000;
+
+// d-pad and left stick drive the 5/7/8/9 cross, face buttons 6 and 4
+const DEFAULT_BUTTONS: [(Button, usize); 6] =
+[
+    (Button::DPadUp, 0x5), (Button::DPadLeft, 0x7), (Button::DPadDown, 0x8), (Button::DPadRight, 0x9),
+    (Button::A, 0x6),      (Button::B, 0x4),
+];
+
+const DEFAULT_AXES: [((Axis, bool), usize); 4] =
+[
+    ((Axis::LeftY, false), 0x5), ((Axis::LeftX, false), 0x7), ((Axis::LeftY, true), 0x8), ((Axis::LeftX, true), 0x9),
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Input
+{
+    Button(Button),
+    Axis(Axis, bool), // axis pushed towards its positive or negative end
+}
+
+// opened game controllers and their mapping to CHIP-8 keys. the mapping is
+// read from the [controller] section, entries use the names of SDL controller
+// mappings, axes take a +/- suffix:
+//
+//   [controller]
+//   deadzone = 8000
+//   dpup = 1
+//   lefty- = 1
+//   a = 5
+pub struct Controllers
+{
+    subsystem: GameControllerSubsystem,
+    opened: HashMap<i32, GameController>,
+
+    buttons: HashMap<Button, usize>,
+    axes: HashMap<(Axis, bool), usize>,
+    deadzone: i16,
+
+    // inputs currently held down, with the controller they belong to
+    held: Vec<(i32, Input, usize)>,
+}
+
+impl Controllers
+{
+    pub fn new(subsystem: GameControllerSubsystem, config: &Config) -> Result<Controllers, String>
+    {
+        let mut controllers = Controllers
+        {
+            subsystem,
+            opened: HashMap::new(),
+
+            buttons: DEFAULT_BUTTONS.iter().cloned().collect(),
+            axes: DEFAULT_AXES.iter().cloned().collect(),
+            deadzone: DEFAULT_DEADZONE,
+
+            held: Vec::new(),
+        };
+
+        if let Some(section) = config.section("controller")
+        {
+            // a profile replaces the default mapping as a whole
+            if section.entries.iter().any(|e| e.0 != "deadzone")
+            {
+                controllers.buttons.clear();
+                controllers.axes.clear();
+            }
+
+            for (name, value) in &section.entries
+            {
+                if name == "deadzone"
+                {
+                    controllers.deadzone = parse_deadzone(value).ok_or(format!("[controller]: invalid deadzone {}", value))?;
+                    continue;
+                }
+
+                let key = keymap::parse_key(value).ok_or(format!("[controller]: invalid CHIP-8 key {}", value))?;
+                match parse_input(name)
+                {
+                    Some(Input::Button(button)) => { controllers.buttons.insert(button, key); },
+                    Some(Input::Axis(axis, positive)) => { controllers.axes.insert((axis, positive), key); },
+                    None => return Err(format!("[controller]: unknown input {}", name)),
+                }
+            }
+        }
+        Ok(controllers)
+    }
+
+    // handles controller events, including devices being plugged in and out
+    pub fn handle_event(&mut self, event: &Event, vm: &mut VM)
+    {
+        match *event
+        {
+            // also sent at startup for the controllers already connected
+            Event::ControllerDeviceAdded { which, .. } =>
+            {
+                match self.subsystem.open(which)
+                {
+                    Ok(controller) =>
+                    {
+                        println!("controller connected: {}", controller.name());
+                        self.opened.insert(controller.instance_id(), controller);
+                    },
+                    Err(e) => println!("failed to open controller {}: {}", which, e),
+                }
+            },
+
+            Event::ControllerDeviceRemoved { which, .. } =>
+            {
+                if let Some(controller) = self.opened.remove(&which)
+                {
+                    println!("controller disconnected: {}", controller.name());
+                }
+
+                let released: Vec<_> = self.held.iter().filter(|h| h.0 == which).map(|h| h.1).collect();
+                for input in released
+                {
+                    self.release(which, input, vm);
+                }
+            },
+
+            Event::ControllerButtonDown { which, button, .. } =>
+            {
+                if let Some(&k) = self.buttons.get(&button)
+                {
+                    self.press(which, Input::Button(button), k, vm);
+                }
+            },
+
+            Event::ControllerButtonUp { which, button, .. } =>
+            {
+                self.release(which, Input::Button(button), vm);
+            },
+
+            Event::ControllerAxisMotion { which, axis, value, .. } =>
+            {
+                for &positive in &[false, true]
+                {
+                    let input = Input::Axis(axis, positive);
+                    let pushed = if positive { value > self.deadzone } else { value < -self.deadzone };
+
+                    match self.axes.get(&(axis, positive))
+                    {
+                        Some(&k) if pushed => self.press(which, input, k, vm),
+                        _ => self.release(which, input, vm),
+                    }
+                }
+            },
+
+            _ => {}
+        }
+    }
+
+    fn press(&mut self, which: i32, input: Input, k: usize, vm: &mut VM)
+    {
+        if !self.held.iter().any(|h| h.0 == which && h.1 == input)
+        {
+            self.held.push((which, input, k));
+        }
+        vm.press_key(k);
+    }
+
+    fn release(&mut self, which: i32, input: Input, vm: &mut VM)
+    {
+        if let Some(pos) = self.held.iter().position(|h| h.0 == which && h.1 == input)
+        {
+            let (_, _, k) = self.held.remove(pos);
+
+            // another input may still be holding the same key
+            if !self.held.iter().any(|h| h.2 == k)
+            {
+                vm.release_key(k);
+            }
+        }
+    }
+}
+
+// distance from the center, 0 to 32767
+fn parse_deadzone(value: &str) -> Option<i16>
+{
+    value.parse().ok().filter(|&deadzone: &i16| deadzone >= 0)
+}
+
+fn parse_input(name: &str) -> Option<Input>
+{
+    if name.ends_with('+') || name.ends_with('-')
+    {
+        let axis = Axis::from_string(&name[..name.len() - 1])?;
+        return Some(Input::Axis(axis, name.ends_with('+')));
+    }
+
+    // triggers only move in one direction
+    match Axis::from_string(name)
+    {
+        Some(axis) => Some(Input::Axis(axis, true)),
+        None => Button::from_string(name).map(Input::Button),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn deadzone()
+    {
+        assert_eq!(parse_deadzone("8000"), Some(8000));
+        assert_eq!(parse_deadzone("0"), Some(0));
+        assert_eq!(parse_deadzone("32767"), Some(32767));
+
+        // the negative end of an axis is compared against -deadzone
+        assert_eq!(parse_deadzone("-1"), None);
+        assert_eq!(parse_deadzone("-32768"), None);
+        assert_eq!(parse_deadzone("32768"), None);
+    }
+}
@@ -0,0 +1,407 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::disasm;
+
+// 3XNN, 4XNN, 5XY0, 9XY0, EX9E and EXA1 skip the next instruction
+pub fn is_skip(opcode: u16) -> bool
+{
+    match opcode & 0xF000
+    {
+        0x3000 | 0x4000 => true,
+        0x5000 | 0x9000 => opcode & 0x000F == 0,
+        0xE000 => opcode & 0x00FF == 0x9E || opcode & 0x00FF == 0xA1,
+        _ => false,
+    }
+}
+
+// execution counts per address, and for skip instructions how often the
+// skip was taken and not taken
+pub struct Coverage
+{
+    hits: Vec<u32>,
+    taken: Vec<u32>,
+    not_taken: Vec<u32>,
+}
+
+impl Coverage
+{
+    pub fn new(size: usize) -> Coverage
+    {
+        Coverage { hits: vec![0; size], taken: vec![0; size], not_taken: vec![0; size] }
+    }
+
+    // called by the VM after executing `opcode` at `pc`, `next_pc` being
+    // where execution continues. the address wraps around memory like the
+    // fetch does
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16)
+    {
+        let at = pc as usize % self.hits.len();
+        self.hits[at] = self.hits[at].saturating_add(1);
+
+        if is_skip(opcode)
+        {
+            if next_pc == pc.wrapping_add(4)
+            {
+                self.taken[at] = self.taken[at].saturating_add(1);
+            }
+            else
+            {
+                self.not_taken[at] = self.not_taken[at].saturating_add(1);
+            }
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u32
+    {
+        self.hits[address as usize]
+    }
+}
+
+// addresses of labels and source lines, one entry per line:
+//
+//   # comment
+//   202 main
+//   20A draw_paddle
+//   202 pong.8o:12
+//
+// addresses are hex, an entry with a colon maps the code starting at the
+// address to a line of an assembler source file
+pub struct Symbols
+{
+    labels: BTreeMap<u16, String>,
+    lines: BTreeMap<u16, (String, usize)>,
+}
+
+impl Symbols
+{
+    pub fn load(filename: &str) -> Result<Symbols, String>
+    {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut symbols = Symbols::parse(&text).map_err(|e| format!("{}: {}", filename, e))?;
+
+        // source files are relative to the symbol map
+        let base = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+        for line in symbols.lines.values_mut()
+        {
+            line.0 = base.join(&line.0).to_string_lossy().into_owned();
+        }
+        Ok(symbols)
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String>
+    {
+        let mut symbols = Symbols { labels: BTreeMap::new(), lines: BTreeMap::new() };
+
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';')
+            {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let address = fields.next().unwrap_or("");
+            let name = fields.next().ok_or(format!("line {}: expected an address and a name", number + 1))?;
+            let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("line {}: invalid address {}", number + 1, address))?;
+
+            match name.rfind(':')
+            {
+                Some(pos) =>
+                {
+                    let source_line = name[pos + 1..].parse().map_err(|_| format!("line {}: invalid source line {}", number + 1, name))?;
+                    symbols.lines.insert(address, (name[..pos].to_string(), source_line));
+                },
+                None =>
+                {
+                    symbols.labels.insert(address, name.to_string());
+                },
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str>
+    {
+        self.labels.get(&address).map(|l| l.as_str())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status
+{
+    None,
+    Hit,
+    Partial,
+    Missed,
+}
+
+struct Line
+{
+    status: Status,
+    count: Option<u32>,
+    text: String,
+}
+
+// writes the report as HTML if the file name ends in .html, as text otherwise.
+//
+// the program is disassembled linearly from `start` to `end`, so data mixed
+// with the code shows up as instructions that were never executed. with
+// source lines in the symbols the listing is the source instead
+pub fn write_report(filename: &str, coverage: &Coverage, memory: &[u8], start: u16, end: u16,
+    symbols: Option<&Symbols>) -> Result<(), String>
+{
+    let instructions = sweep(coverage, start, end);
+
+    let executed = instructions.iter().filter(|&&a| coverage.hits(a) > 0).count();
+    let skips: Vec<u16> = instructions.iter().cloned().filter(|&a| is_skip(opcode(memory, a))).collect();
+    let outcomes: usize = skips.iter()
+        .map(|&a| (coverage.taken[a as usize] > 0) as usize + (coverage.not_taken[a as usize] > 0) as usize)
+        .sum();
+
+    let summary = format!("{}/{} instructions executed ({:.1}%), {}/{} skip outcomes covered",
+        executed, instructions.len(), percent(executed, instructions.len()), outcomes, skips.len() * 2);
+
+    let lines = match symbols
+    {
+        Some(symbols) if !symbols.lines.is_empty() => source_listing(coverage, memory, &instructions, symbols)?,
+        _ => disassembly_listing(coverage, memory, &instructions, symbols),
+    };
+
+    let report = if filename.to_lowercase().ends_with(".html")
+    {
+        html(&summary, &lines)
+    }
+    else
+    {
+        text(&summary, &lines)
+    };
+
+    fs::write(filename, report).map_err(|e| format!("{}: {}", filename, e))
+}
+
+// instruction addresses between start and end. an instruction normally
+// starts every two bytes, unless execution shows that the code is shifted by
+// one, in which case the byte in between is data
+fn sweep(coverage: &Coverage, start: u16, end: u16) -> Vec<u16>
+{
+    let mut addresses = Vec::new();
+    let mut address = start;
+    while address + 1 < end
+    {
+        if coverage.hits(address) == 0 && coverage.hits(address + 1) > 0
+        {
+            address += 1;
+            continue;
+        }
+        addresses.push(address);
+        address += 2;
+    }
+    addresses
+}
+
+fn opcode(memory: &[u8], address: u16) -> u16
+{
+    (memory[address as usize] as u16) << 8 | memory[address as usize + 1] as u16
+}
+
+fn percent(part: usize, total: usize) -> f64
+{
+    if total == 0 { 100.0 } else { part as f64 * 100.0 / total as f64 }
+}
+
+// status of one instruction, a skip counts as partial unless it went both ways
+fn status(coverage: &Coverage, memory: &[u8], address: u16) -> Status
+{
+    let at = address as usize;
+    if coverage.hits[at] == 0
+    {
+        Status::Missed
+    }
+    else if is_skip(opcode(memory, address)) && (coverage.taken[at] == 0 || coverage.not_taken[at] == 0)
+    {
+        Status::Partial
+    }
+    else
+    {
+        Status::Hit
+    }
+}
+
+fn disassembly_listing(coverage: &Coverage, memory: &[u8], instructions: &[u16], symbols: Option<&Symbols>) -> Vec<Line>
+{
+    let mut lines = Vec::new();
+    for &address in instructions
+    {
+        if let Some(label) = symbols.and_then(|s| s.labels.get(&address))
+        {
+            lines.push(Line { status: Status::None, count: None, text: format!("{}:", label) });
+        }
+
+        let opcode = opcode(memory, address);
+        let mut text = format!("{:03X}  {:04X}  {:<16}", address, opcode, disasm::disassemble(opcode)).trim_end().to_string();
+        if is_skip(opcode)
+        {
+            text = format!("{:<28}  skipped {}, not skipped {}", text, coverage.taken[address as usize], coverage.not_taken[address as usize]);
+        }
+
+        lines.push(Line { status: status(coverage, memory, address), count: Some(coverage.hits(address)), text });
+    }
+    lines
+}
+
+// every source line gets the instructions from its address up to the next
+// mapped address, and the count of the first of them
+fn source_listing(coverage: &Coverage, memory: &[u8], instructions: &[u16], symbols: &Symbols) -> Result<Vec<Line>, String>
+{
+    let mut per_line: BTreeMap<(String, usize), (u32, Status)> = BTreeMap::new();
+
+    let mapped: Vec<(&u16, &(String, usize))> = symbols.lines.iter().collect();
+    for (i, (&address, location)) in mapped.iter().enumerate()
+    {
+        let next = mapped.get(i + 1).map_or(u16::MAX, |m| *m.0);
+        let covered: Vec<u16> = instructions.iter().cloned().filter(|&a| a >= address && a < next).collect();
+        if covered.is_empty()
+        {
+            continue;
+        }
+
+        let statuses: Vec<Status> = covered.iter().map(|&a| status(coverage, memory, a)).collect();
+        let combined = if statuses.iter().all(|&s| s == Status::Hit)
+        {
+            Status::Hit
+        }
+        else if statuses.iter().all(|&s| s == Status::Missed)
+        {
+            Status::Missed
+        }
+        else
+        {
+            Status::Partial
+        };
+
+        let entry = per_line.entry((*location).clone()).or_insert((0, combined));
+        entry.0 += coverage.hits(covered[0]);
+        if entry.1 != combined
+        {
+            entry.1 = Status::Partial;
+        }
+    }
+
+    let mut files: Vec<&String> = symbols.lines.values().map(|l| &l.0).collect();
+    files.sort();
+    files.dedup();
+
+    let mut lines = Vec::new();
+    for file in files
+    {
+        let source = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        lines.push(Line { status: Status::None, count: None, text: format!("--- {}", file) });
+
+        for (number, text) in source.lines().enumerate()
+        {
+            let text = format!("{:5}  {}", number + 1, text);
+            lines.push(match per_line.get(&(file.clone(), number + 1))
+            {
+                Some(&(count, status)) => Line { status, count: Some(count), text },
+                None => Line { status: Status::None, count: None, text },
+            });
+        }
+    }
+    Ok(lines)
+}
+
+// gcov style: execution count, ##### for code that never ran and a * after
+// the count of partially covered code
+fn text(summary: &str, lines: &[Line]) -> String
+{
+    let mut report = format!("; {}\n", summary);
+    for line in lines
+    {
+        let count = match (line.status, line.count)
+        {
+            (Status::Missed, _) => "#####".to_string(),
+            (Status::Partial, Some(count)) => format!("{}*", count),
+            (_, Some(count)) => count.to_string(),
+            (_, None) => "-".to_string(),
+        };
+        report += &format!("{:>10}  {}\n", count, line.text);
+    }
+    report
+}
+
+fn html(summary: &str, lines: &[Line]) -> String
+{
+    let mut report = String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>dale8 coverage</title>\n\
+        <style>body { background: #101018; color: #c0c0c0; } pre { font-family: monospace; }\n\
+        .hit { background: #103010; } .partial { background: #403010; } .missed { background: #401010; }\n\
+        .count { color: #707078; }</style></head><body>\n");
+    report += &format!("<p>{}</p>\n<pre>\n", escape(summary));
+
+    for line in lines
+    {
+        let class = match line.status
+        {
+            Status::None => "",
+            Status::Hit => "hit",
+            Status::Partial => "partial",
+            Status::Missed => "missed",
+        };
+        let count = line.count.map_or(String::new(), |c| c.to_string());
+        report += &format!("<span class=\"{}\"><span class=\"count\">{:>10}</span>  {}</span>\n", class, count, escape(&line.text));
+    }
+
+    report += "</pre>\n</body></html>\n";
+    report
+}
+
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn skip_outcomes()
+    {
+        let mut coverage = Coverage::new(0x1000);
+        coverage.record(0x200, 0x3000, 0x204);
+        coverage.record(0x200, 0x3000, 0x202);
+        coverage.record(0x204, 0x6000, 0x206);
+
+        assert_eq!(coverage.hits(0x200), 2);
+        assert_eq!((coverage.taken[0x200], coverage.not_taken[0x200]), (1, 1));
+        assert_eq!((coverage.taken[0x204], coverage.not_taken[0x204]), (0, 0));
+    }
+
+    #[test]
+    fn past_the_end_of_memory()
+    {
+        // the fetch wraps around a 4K layout, so does the count
+        let mut coverage = Coverage::new(0x1000);
+        coverage.record(0x1002, 0x4000, 0x1006);
+        assert_eq!(coverage.hits(0x002), 1);
+        assert_eq!(coverage.taken[0x002], 1);
+    }
+}
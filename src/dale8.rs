@@ -25,12 +25,25 @@
 // Copyright (C) 2019 Daniel Pistelli / ntcore.com
 ///////////////////////////////////////////////////////////////////////////////
 
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::prelude::*;
 use rand;
 
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::coverage::{self, Coverage};
+use crate::profiler::Profiler;
+use crate::timing::{self, Timing};
 
-const FONTSET: [u8; 80] = 
+
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+
+// the delay and sound timers count down at 60 Hz
+pub const TIMER_FREQUENCY: u32 = 60;
+
+// 4x5 hexadecimal digits for FX29, the font of CHIP-48 and most interpreters
+pub const FONTSET: [u8; 80] = 
 [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -50,6 +63,158 @@ const FONTSET: [u8; 80] =
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+// 8x10 hexadecimal digits for FX30, as in XO-CHIP. they follow the small
+// font in memory
+pub const BIG_FONTSET: [u8; 160] =
+[
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+// behaviours that differ between interpreters, the defaults are the ones
+// this VM always had
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks
+{
+    pub shift: bool,    // 8XY6/8XYE shift VX in place instead of shifting VY into VX
+    pub memory: bool,   // FX55/FX65 leave I pointing after the last register
+    pub jump: bool,     // BNNN jumps to XNN plus VX instead of NNN plus V0
+    pub vf_reset: bool, // 8XY1, 8XY2 and 8XY3 clear VF
+    pub display_wait: bool, // DXYN waits for the next frame before drawing
+    pub key_release: bool,  // FX0A waits for the key to be released, as on the VIP
+}
+
+impl Default for Quirks
+{
+    fn default() -> Quirks
+    {
+        Quirks { shift: true, memory: true, jump: false, vf_reset: false, display_wait: false, key_release: false }
+    }
+}
+
+// a key going down or up, as sent by the frontends
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeyEvent
+{
+    Press(u8),
+    Release(u8),
+}
+
+// events older than these are dropped when nothing reads them
+const KEY_EVENTS: usize = 16;
+
+// progress of FX0A
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum KeyWait
+{
+    Idle,
+    Waiting,  // for a key to be pressed
+    Held(u8), // with the key release quirk, for the pressed key to be released
+}
+
+// where things are in memory. the program is loaded at program_start and
+// may not grow into the area the interpreter reserves at the top
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryLayout
+{
+    pub program_start: u16,
+    pub font_base: u16,
+    pub size: usize,
+    pub reserved: usize, // bytes at the end of memory
+}
+
+impl Default for MemoryLayout
+{
+    fn default() -> MemoryLayout
+    {
+        MemoryLayout { program_start: 0x200, font_base: 0x000, size: 4096, reserved: 0 }
+    }
+}
+
+// where the VIP interpreter keeps its state, counted from the end of memory:
+// the stack from 0xECF down, V0-VF at 0xEF0 and the display at 0xF00 on a
+// 4 KiB machine
+const VIP_STACK: usize = 0x131;
+const VIP_VARIABLES: usize = 0x110;
+const VIP_DISPLAY: usize = 0x100;
+
+// machine cycles a routine runs before giving the frame back, it resumes on
+// the next cycle
+const MACHINE_CODE_SLICE: u32 = timing::VIP_CYCLES_PER_FRAME;
+
+// EF1 is active for the 4 lines of 14 machine cycles before the display
+const EF1_CYCLES: u32 = 4 * 14;
+
+// the VIP hardware seen by machine code routines: memory, the keypad where
+// OUT 2 selects a key and EF3 tells whether it's pressed, and EF1 telling
+// that the display is about to start
+struct Keypad<'a>
+{
+    memory: &'a mut [u8],
+    key: &'a [u8; 16],
+    selected: &'a mut usize,
+    ef1: u32, // machine cycles it stays active
+}
+
+impl<'a> Bus for Keypad<'a>
+{
+    fn read(&mut self, address: u16) -> u8
+    {
+        self.memory[address as usize % self.memory.len()]
+    }
+
+    fn write(&mut self, address: u16, value: u8)
+    {
+        let size = self.memory.len();
+        self.memory[address as usize % size] = value;
+    }
+
+    fn input(&mut self, _port: u8) -> u8
+    {
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8)
+    {
+        if port == 2
+        {
+            *self.selected = (value & 0xF) as usize;
+        }
+    }
+
+    fn flag(&mut self, number: u8) -> bool
+    {
+        match number
+        {
+            1 => self.ef1 > 0,
+            3 => self.key[*self.selected] != 0,
+            _ => false,
+        }
+    }
+}
+
+// number of times each address was read, written and executed
+pub struct AccessCounts
+{
+    pub reads: Vec<u32>,
+    pub writes: Vec<u32>,
+    pub executes: Vec<u32>,
+}
+
 pub struct VM
 {
     pc: u16,
@@ -59,16 +224,46 @@ pub struct VM
 
     v: [u8; 16],
     stack: [u16; 16],
-    memory: [u8; 4096],
+    memory: Vec<u8>,
+    layout: MemoryLayout,
+    font: Vec<u8>, // small font followed by the big one
 
     pub gfx: [u8; 2048],
+
+    // keys held down. press_key and release_key also queue the events FX0A
+    // needs to see taps and releases, keys written here directly only count
+    // while held
     pub key: [u8; 16],
+    key_events: VecDeque<KeyEvent>, // since FX0A started waiting
+    key_wait: KeyWait,
 
     delay_timer: u8,
     sound_timer: u8,
 
     pub draw_flag: bool,
     pub beep_flag: bool,
+
+    pub quirks: Quirks,
+    pub timing: Timing,
+    cycles: u32, // taken by the last instruction
+
+    // with the display wait quirk, a DXYN waiting for the frame to end, and
+    // whether the frame has ended since
+    waiting_for_vblank: bool,
+    vblank: bool,
+
+    // only kept when enabled, it slows down every memory access
+    access_counts: Option<Box<AccessCounts>>,
+    coverage: Option<Box<Coverage>>,
+    profiler: Option<Box<Profiler>>,
+
+    // runs 0NNN machine code routines when present
+    cpu: Option<Box<Cdp1802>>,
+    in_machine_code: bool,   // a routine that gave the frame back, resumed by the next cycle
+    display_interrupt: bool, // a frame has ended since the routine last ran
+    selected_key: usize,     // latched by OUT 2
+
+    rom_size: usize,
 }
 
 impl VM
@@ -77,23 +272,45 @@ impl VM
     {
         let mut vm = VM 
         { 
-            pc: 0x200, // starts at 0x200
+            pc: 0x200, // starts at 0x200, or wherever the layout says
             opcode: 0,
             ir: 0,
             sp: 0,
 
             v: [0; 16],
             stack: [0; 16],
-            memory: [0; 4096],
+            memory: vec![0; 4096],
+            layout: MemoryLayout::default(),
+            font: FONTSET.iter().chain(BIG_FONTSET.iter()).cloned().collect(),
 
             gfx: [0; 2048],
             key: [0; 16],
+            key_events: VecDeque::new(),
+            key_wait: KeyWait::Idle,
 
             delay_timer: 0,
             sound_timer: 0,
 
             draw_flag: true,
             beep_flag: false,
+
+            quirks: Quirks::default(),
+            timing: Timing::Uniform,
+            cycles: 0,
+
+            waiting_for_vblank: false,
+            vblank: false,
+
+            access_counts: None,
+            coverage: None,
+            profiler: None,
+
+            cpu: None,
+            in_machine_code: false,
+            display_interrupt: false,
+            selected_key: 0,
+
+            rom_size: 0,
         };
 
         // load fontset
@@ -101,25 +318,237 @@ impl VM
         {
             vm.memory[i] = FONTSET[i];
         }
+        vm.memory[80..240].copy_from_slice(&BIG_FONTSET);
 
         return vm;
     }
 
+    // switches to another memory layout, moving the loaded program to the
+    // new program start. meant to be called before running anything
+    pub fn set_layout(& mut self, layout: MemoryLayout) -> Result<(), String>
+    {
+        let font_end = layout.font_base as usize + self.font.len();
+        if layout.size > 0x10000 || font_end > layout.size || layout.program_start as usize + layout.reserved >= layout.size
+        {
+            return Err(format!("invalid memory layout {:?}", layout));
+        }
+
+        let (start, end) = self.program_range();
+        let rom = self.memory[start as usize..end as usize].to_vec();
+
+        self.memory = vec![0; layout.size];
+        self.memory[layout.font_base as usize..font_end].copy_from_slice(&self.font);
+        self.layout = layout;
+        self.pc = layout.program_start;
+        self.load_rom_bytes(&rom)
+    }
+
+    // replaces the 80-byte small and 160-byte big font
+    pub fn set_font(& mut self, small: &[u8], big: &[u8]) -> Result<(), String>
+    {
+        if small.len() != FONTSET.len() || big.len() != BIG_FONTSET.len()
+        {
+            return Err(format!("a font has {} bytes of small and {} bytes of big digits", FONTSET.len(), BIG_FONTSET.len()));
+        }
+
+        self.font = small.iter().chain(big.iter()).cloned().collect();
+        let base = self.layout.font_base as usize;
+        self.memory[base..base + self.font.len()].copy_from_slice(&self.font);
+        Ok(())
+    }
+
+    // 0NNN calls run on an RCA 1802 instead of stopping the emulation
+    pub fn enable_machine_code(& mut self)
+    {
+        if self.cpu.is_none()
+        {
+            self.cpu = Some(Box::new(Cdp1802::new()));
+        }
+    }
+
+    // runs the routine at `address` the way the VIP interpreter calls it:
+    // with the registers, I, the timers and the display where the
+    // interpreter keeps them and R3 as the program counter, until it returns
+    // to the interpreter with SEP R4. returns the machine cycles it took.
+    //
+    // a routine polling the keypad or waiting for the display gives the
+    // frame back after MACHINE_CODE_SLICE cycles or at IDL. the 0NNN is then
+    // left in place and continues the routine, which sees the keys and
+    // timers of the next frame
+    fn call_machine_code(& mut self, address: u16) -> u32
+    {
+        let size = self.memory.len();
+        let (stack, variables, display) = (size.saturating_sub(VIP_STACK), size.saturating_sub(VIP_VARIABLES), size.saturating_sub(VIP_DISPLAY));
+
+        let mut cpu = self.cpu.take().unwrap_or_default();
+        let interrupt = self.display_interrupt;
+        self.display_interrupt = false;
+
+        if self.in_machine_code
+        {
+            // the display interrupt counts the timers down, and ends IDL
+            cpu.r[8] = (self.delay_timer as u16) << 8 | self.sound_timer as u16;
+            cpu.idle = cpu.idle && !interrupt;
+        }
+        else
+        {
+            self.memory[variables..variables + 16].copy_from_slice(&self.v);
+            for (byte, pixels) in self.memory[display..].iter_mut().zip(self.gfx.chunks(8))
+            {
+                *byte = pixels.iter().fold(0, |byte, &pixel| byte << 1 | (pixel != 0) as u8);
+            }
+
+            cpu.p = 3;
+            cpu.x = 2;
+            cpu.idle = false;
+            cpu.r[2] = stack as u16;
+            cpu.r[3] = address;
+            cpu.r[5] = self.pc + 2;
+            cpu.r[6] = (variables + ((self.opcode & 0x0F00) >> 8) as usize) as u16;
+            cpu.r[7] = (variables + ((self.opcode & 0x00F0) >> 4) as usize) as u16;
+            cpu.r[8] = (self.delay_timer as u16) << 8 | self.sound_timer as u16;
+            cpu.r[10] = self.ir;
+            cpu.r[11] = display as u16;
+        }
+
+        let mut keypad = Keypad
+        {
+            memory: &mut self.memory,
+            key: &self.key,
+            selected: &mut self.selected_key,
+            ef1: if interrupt && self.in_machine_code { EF1_CYCLES } else { 0 },
+        };
+        let mut cycles = 0;
+        while cpu.p != 4 && cycles < MACHINE_CODE_SLICE && !cpu.idle
+        {
+            let taken = cpu.step(&mut keypad);
+            keypad.ef1 = keypad.ef1.saturating_sub(taken);
+            cycles += taken;
+        }
+        self.in_machine_code = cpu.p != 4;
+
+        self.v.copy_from_slice(&self.memory[variables..variables + 16]);
+        for (&byte, pixels) in self.memory[display..].iter().zip(self.gfx.chunks_mut(8))
+        {
+            for (i, pixel) in pixels.iter_mut().enumerate()
+            {
+                *pixel = (byte >> (7 - i)) & 1;
+            }
+        }
+        self.delay_timer = (cpu.r[8] >> 8) as u8;
+        self.sound_timer = cpu.r[8] as u8;
+        self.ir = cpu.r[10];
+        if !self.in_machine_code
+        {
+            self.pc = cpu.r[5];
+        }
+        self.draw_flag = true;
+        self.cpu = Some(cpu);
+        cycles
+    }
+
+    // memory accesses of instructions, counted when enabled
+    // addresses wrap around at the end of memory
+    fn read(& mut self, address: u16) -> u8
+    {
+        let address = address as usize % self.memory.len();
+        if let Some(ref mut counts) = self.access_counts
+        {
+            counts.reads[address] = counts.reads[address].wrapping_add(1);
+        }
+        self.memory[address]
+    }
+
+    fn write(& mut self, address: u16, value: u8)
+    {
+        let address = address as usize % self.memory.len();
+        if let Some(ref mut counts) = self.access_counts
+        {
+            counts.writes[address] = counts.writes[address].wrapping_add(1);
+        }
+        self.memory[address] = value;
+    }
+
+    pub fn enable_access_counts(& mut self)
+    {
+        if self.access_counts.is_none()
+        {
+            let size = self.memory.len();
+            self.access_counts = Some(Box::new(AccessCounts
+            {
+                reads: vec![0; size],
+                writes: vec![0; size],
+                executes: vec![0; size],
+            }));
+        }
+    }
+
+    pub fn access_counts(& self) -> Option<&AccessCounts>
+    {
+        self.access_counts.as_deref()
+    }
+
+    pub fn enable_coverage(& mut self)
+    {
+        if self.coverage.is_none()
+        {
+            self.coverage = Some(Box::new(Coverage::new(self.memory.len())));
+        }
+    }
+
+    pub fn coverage(& self) -> Option<&Coverage>
+    {
+        self.coverage.as_deref()
+    }
+
+    pub fn enable_profiler(& mut self)
+    {
+        if self.profiler.is_none()
+        {
+            self.profiler = Some(Box::new(Profiler::new(self.memory.len(), self.pc)));
+        }
+    }
+
+    pub fn profiler(& self) -> Option<&Profiler>
+    {
+        self.profiler.as_deref()
+    }
+
     pub fn emulate_cycle(& mut self)
     {
+        if self.waiting_for_vblank
+        {
+            self.cycles = 0;
+            return;
+        }
+
+        let pc = self.pc;
+
         // fetch opcode
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize] as u16);
+        let (high, low) = (self.pc as usize % self.memory.len(), (self.pc as usize + 1) % self.memory.len());
+        self.opcode = (self.memory[high] as u16) << 8 | (self.memory[low] as u16);
+        if let Some(ref mut counts) = self.access_counts
+        {
+            counts.executes[high] = counts.executes[high].wrapping_add(1);
+            counts.executes[low] = counts.executes[low].wrapping_add(1);
+        }
 
         //println!("opcode: {:02X}{:02X}", (self.opcode >> 8) as u8, self.opcode as u8);
 
+        self.cycles = match self.timing
+        {
+            Timing::Uniform => 1,
+            Timing::Vip => timing::vip_cycles(self.opcode, &self.v, self.ir),
+        };
+
         // process opcode
         match self.opcode & 0xF000
         {
             0x0000 =>
             {
-                match self.opcode & 0x000F
+                match self.opcode
                 {
-                    0x0000 => // 0x00E0: clears the screen
+                    0x00E0 => // 0x00E0: clears the screen
                     {
                         for i in 0..2048 
                         {
@@ -128,12 +557,20 @@ impl VM
                         self.draw_flag = true;
                         self.pc += 2;
                     },
-                    0x000E => // 0x00EE: returns from subroutine
+                    0x00EE => // 0x00EE: returns from subroutine
                     {
                         self.sp -= 1;                           // 16 levels of stack, decrease stack pointer to prevent overwrite
                         self.pc = self.stack[self.sp as usize]; // put the stored return address from the stack back into the program counter           
                         self.pc += 2                            // don't forget to increase the program counter!
                     }
+                    _ if self.cpu.is_some() => // 0x0NNN: calls the machine code routine at NNN
+                    {
+                        let cycles = self.call_machine_code(self.opcode & 0x0FFF);
+                        if self.timing == Timing::Vip
+                        {
+                            self.cycles += cycles;
+                        }
+                    },
                     _ => 
                     {
                         panic!("unknown opcode [0x0000]: 0x{:X}.", self.opcode);
@@ -215,78 +652,86 @@ impl VM
                     0x0001 => // 0x8XY1: sets VX to "VX OR VY"
                     {
                         self.v[((self.opcode & 0x0F00) >> 8) as usize] |= self.v[((self.opcode & 0x00F0) >> 4) as usize];
+                        if self.quirks.vf_reset
+                        {
+                            self.v[0xF] = 0;
+                        }
                         self.pc += 2;
                     },
 
                     0x0002 => // 0x8XY2: sets VX to "VX AND VY"
                     {
                         self.v[((self.opcode & 0x0F00) >> 8) as usize] &= self.v[((self.opcode & 0x00F0) >> 4) as usize];
+                        if self.quirks.vf_reset
+                        {
+                            self.v[0xF] = 0;
+                        }
                         self.pc += 2;
                     },
 
                     0x0003 => // 0x8XY3: sets VX to "VX XOR VY"
                     {
                         self.v[((self.opcode & 0x0F00) >> 8) as usize] ^= self.v[((self.opcode & 0x00F0) >> 4) as usize];
+                        if self.quirks.vf_reset
+                        {
+                            self.v[0xF] = 0;
+                        }
                         self.pc += 2;
                     },
 
                     0x0004 => // 0x8XY4: adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't
                     {
-                        if self.v[((self.opcode & 0x00F0) >> 4) as usize] > (0xFF - self.v[((self.opcode & 0x0F00) >> 8) as usize])
-                        {
-                            self.v[0xF] = 1; // carry
-                        }
-                        else
-                        { 
-                            self.v[0xF] = 0;
-                        }
                         let pos: usize = ((self.opcode & 0x0F00) >> 8) as usize;        
+                        let carry = self.v[((self.opcode & 0x00F0) >> 4) as usize] > (0xFF - self.v[pos]);
                         self.v[pos] = self.v[pos].wrapping_add(self.v[((self.opcode & 0x00F0) >> 4) as usize]);
+
+                        // the flag is written last, also when VX is VF
+                        self.v[0xF] = carry as u8;
                         self.pc += 2;
                     },
 
                     0x0005 => // 0x8XY5: VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there isn't
                     {
                         let pos: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-                        if self.v[((self.opcode & 0x00F0) >> 4) as usize] > self.v[pos]
-                        {
-                            self.v[0xF] = 0; // there is a borrow
-                        }
-                        else
-                        {
-                            self.v[0xF] = 1;
-                        }
+                        let borrow = self.v[((self.opcode & 0x00F0) >> 4) as usize] > self.v[pos];
                         self.v[pos] = self.v[pos].wrapping_sub(self.v[((self.opcode & 0x00F0) >> 4) as usize]);
+
+                        // the flag is written last, also when VX is VF
+                        self.v[0xF] = !borrow as u8;
                         self.pc += 2;
                     },
 
                     0x0006 => // 0x8XY6: shifts VX right by one. VF is set to the value of the least significant bit of VX before the shift
                     {
-                        self.v[0xF] = self.v[((self.opcode & 0x0F00) >> 8) as usize] & 0x1;
-                        self.v[((self.opcode & 0x0F00) >> 8) as usize] >>= 1;
+                        let source = if self.quirks.shift { (self.opcode & 0x0F00) >> 8 } else { (self.opcode & 0x00F0) >> 4 };
+                        let value = self.v[source as usize];
+                        self.v[((self.opcode & 0x0F00) >> 8) as usize] = value >> 1;
+
+                        // the flag is written last, also when VX is VF
+                        self.v[0xF] = value & 0x1;
                         self.pc += 2;
                     },
 
                     0x0007 => // 0x8XY7: sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there isn't
                     {
                         let pos: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-                        if self.v[pos] > self.v[((self.opcode & 0x00F0) >> 4) as usize] // VY-VX
-                        {
-                            self.v[0xF] = 0; // there is a borrow
-                        }
-                        else
-                        {
-                            self.v[0xF] = 1;
-                        }
+                        let borrow = self.v[pos] > self.v[((self.opcode & 0x00F0) >> 4) as usize]; // VY-VX
                         self.v[pos] = self.v[((self.opcode & 0x00F0) >> 4) as usize].wrapping_sub(self.v[pos]);             
+
+                        // the flag is written last, also when VX is VF
+                        self.v[0xF] = !borrow as u8;
                         self.pc += 2;
 
                     },
 
                     0x000E => // 0x8XYE: shifts VX left by one. VF is set to the value of the most significant bit of VX before the shift
                     {
-                        self.v[0xF] = self.v[((self.opcode & 0x0F00) >> 8) as usize] >> 7;
-                        self.v[((self.opcode & 0x0F00) >> 8) as usize] <<= 1;
+                        let source = if self.quirks.shift { (self.opcode & 0x0F00) >> 8 } else { (self.opcode & 0x00F0) >> 4 };
+                        let value = self.v[source as usize];
+                        self.v[((self.opcode & 0x0F00) >> 8) as usize] = value << 1;
+
+                        // the flag is written last, also when VX is VF
+                        self.v[0xF] = value >> 7;
                         self.pc += 2;
                     }, 
 
@@ -315,9 +760,10 @@ impl VM
                 self.pc += 2;
             },
 
-            0xB000 => // BNNN: jumps to the address NNN plus V0
+            0xB000 => // BNNN: jumps to the address NNN plus V0 (BXNN: plus VX with the jump quirk)
             {
-                self.pc = (self.opcode & 0x0FFF).wrapping_add(self.v[0] as u16);
+                let offset = if self.quirks.jump { self.v[((self.opcode & 0x0F00) >> 8) as usize] } else { self.v[0] };
+                self.pc = (self.opcode & 0x0FFF).wrapping_add(offset as u16);
             },
 
             0xC000 => // CXNN: sets VX to a random number and NN
@@ -333,6 +779,17 @@ impl VM
             // and to 0 if that doesn't happen
             0xD000 =>
             {
+                // the original interpreter draws right after the display
+                // interrupt. until then the instruction is retried. the VIP
+                // timing always models it
+                if (self.quirks.display_wait || self.timing == Timing::Vip) && !self.vblank
+                {
+                    self.waiting_for_vblank = true;
+                    self.cycles = 0;
+                    return;
+                }
+                self.vblank = false;
+
                 let x = self.v[((self.opcode & 0x0F00) >> 8) as usize] as u16;
                 let y = self.v[((self.opcode & 0x00F0) >> 4) as usize] as u16;
                 let height = self.opcode & 0x000F;
@@ -340,7 +797,7 @@ impl VM
                 self.v[0xF] = 0;
                 for yline in 0..height
                 {
-                    let pixel = self.memory[(self.ir + yline) as usize] as u16;
+                    let pixel = self.read(self.ir + yline) as u16;
                     for xline in 0..8
                     {
                         if (pixel & (0x80 >> xline)) != 0
@@ -409,20 +866,47 @@ impl VM
 
                     0x000A => // FX0A: a key press is awaited, and then stored in VX
                     {
-                        let mut key_press = false;
+                        // taps and releases only count after FX0A started
+                        if self.key_wait == KeyWait::Idle
+                        {
+                            self.key_events.clear();
+                            self.key_wait = KeyWait::Waiting;
+                        }
 
-                        for i in 0..16
+                        let mut pressed = None;
+                        if self.quirks.key_release
                         {
-                            if self.key[i] != 0
+                            // the key has to go down and up again, held ones
+                            // don't count. events are read one by one, a tap
+                            // between two cycles isn't missed
+                            while let Some(event) = self.key_events.pop_front()
                             {
-                                self.v[((self.opcode & 0x0F00) >> 8) as usize] = i as u8;
-                                key_press = true;
+                                match (self.key_wait, event)
+                                {
+                                    (KeyWait::Waiting, KeyEvent::Press(k)) => self.key_wait = KeyWait::Held(k),
+                                    (KeyWait::Held(held), KeyEvent::Release(k)) if k == held => pressed = Some(k),
+                                    _ => {},
+                                }
+
+                                if pressed.is_some()
+                                {
+                                    break;
+                                }
                             }
                         }
+                        else
+                        {
+                            // any key held down, the highest one first, or a
+                            // key tapped since the last cycle
+                            pressed = self.key.iter().rposition(|&k| k != 0).map(|k| k as u8).or_else(||
+                                self.key_events.iter().find_map(|&e| match e { KeyEvent::Press(k) => Some(k), _ => None }));
+                        }
 
                         // if we didn't received a keypress, skip this cycle and try again.
-                        if key_press
+                        if let Some(k) = pressed
                         {
+                            self.v[((self.opcode & 0x0F00) >> 8) as usize] = k;
+                            self.key_wait = KeyWait::Idle;
                             self.pc += 2;
                         }
                     },
@@ -456,15 +940,23 @@ impl VM
 
                     0x0029 => // FX29: sets ir to the location of the sprite for the character in VX. Characters 0-F (in hexadecimal) are represented by a 4x5 font
                     {
-                        self.ir = self.v[((self.opcode & 0x0F00) >> 8) as usize] as u16 * 0x5;
+                        self.ir = self.layout.font_base + self.v[((self.opcode & 0x0F00) >> 8) as usize] as u16 * 0x5;
+                        self.pc += 2;
+                    },
+
+                    0x0030 => // FX30: sets ir to the location of the 8x10 sprite for the digit in VX (SCHIP)
+                    {
+                        let digit = self.v[((self.opcode & 0x0F00) >> 8) as usize] & 0xF;
+                        self.ir = self.layout.font_base + FONTSET.len() as u16 + digit as u16 * 10;
                         self.pc += 2;
                     },
 
                     0x0033 => // FX33: stores the binary-coded decimal representation of VX at the addresses ir, ir plus 1, and ir plus 2
                     {
-                        self.memory[self.ir as usize] = self.v[((self.opcode & 0x0F00) >> 8) as usize] / 100;
-                        self.memory[(self.ir + 1) as usize] = (self.v[((self.opcode & 0x0F00) >> 8) as usize] / 10) % 10;
-                        self.memory[(self.ir + 2) as usize] = (self.v[((self.opcode & 0x0F00) >> 8) as usize] % 100) % 10;                  
+                        let value = self.v[((self.opcode & 0x0F00) >> 8) as usize];
+                        self.write(self.ir, value / 100);
+                        self.write(self.ir + 1, (value / 10) % 10);
+                        self.write(self.ir + 2, (value % 100) % 10);
                         self.pc += 2;
                     },
 
@@ -473,11 +965,14 @@ impl VM
                         let j = (self.opcode & 0x0F00) >> 8;
                         for i in 0..j + 1
                         {
-                            self.memory[(self.ir + i) as usize] = self.v[i as usize];
+                            self.write(self.ir + i, self.v[i as usize]);
                         }
 
                         // on the original interpreter, when the operation is done, ir = ir + X + 1.
-                        self.ir = self.ir.wrapping_add(j + 1);
+                        if self.quirks.memory
+                        {
+                            self.ir = self.ir.wrapping_add(j + 1);
+                        }
                         self.pc += 2;
                     },
 
@@ -486,11 +981,14 @@ impl VM
                         let j = (self.opcode & 0x0F00) >> 8;
                         for i in 0..j + 1
                         {
-                            self.v[i as usize] = self.memory[(self.ir + i) as usize];
+                            self.v[i as usize] = self.read(self.ir + i);
                         }
 
                         // on the original interpreter, when the operation is done, ir = ir + X + 1.
-                        self.ir = self.ir.wrapping_add(j + 1);
+                        if self.quirks.memory
+                        {
+                            self.ir = self.ir.wrapping_add(j + 1);
+                        }
                         self.pc += 2;
                     },
 
@@ -507,7 +1005,24 @@ impl VM
             },
         }
 
-        // update timers
+        if self.timing == Timing::Vip && coverage::is_skip(self.opcode) && self.pc == pc.wrapping_add(4)
+        {
+            self.cycles += timing::VIP_SKIP;
+        }
+
+        if let Some(ref mut coverage) = self.coverage
+        {
+            coverage.record(pc, self.opcode, self.pc);
+        }
+        if let Some(ref mut profiler) = self.profiler
+        {
+            profiler.record(pc, self.opcode, self.cycles as u64);
+        }
+    }
+
+    // counts the timers down, the host calls this at TIMER_FREQUENCY
+    pub fn update_timers(& mut self)
+    {
         if self.delay_timer > 0
         {
             self.delay_timer -= 1;
@@ -523,6 +1038,75 @@ impl VM
         }
     }
 
+    // called by the scheduler at the end of each frame, releases a DXYN
+    // waiting for it and interrupts a machine code routine
+    pub fn vblank(& mut self)
+    {
+        self.vblank = self.waiting_for_vblank;
+        self.waiting_for_vblank = false;
+        self.display_interrupt = true;
+    }
+
+    // a key of the keypad going down, from a frontend or a script
+    pub fn press_key(& mut self, key: usize)
+    {
+        if key < 16 && self.key[key] == 0
+        {
+            self.key[key] = 1;
+            self.push_key_event(KeyEvent::Press(key as u8));
+        }
+    }
+
+    pub fn release_key(& mut self, key: usize)
+    {
+        if key < 16 && self.key[key] != 0
+        {
+            self.key[key] = 0;
+            self.push_key_event(KeyEvent::Release(key as u8));
+        }
+    }
+
+    fn push_key_event(& mut self, event: KeyEvent)
+    {
+        if self.key_events.len() == KEY_EVENTS
+        {
+            self.key_events.pop_front();
+        }
+        self.key_events.push_back(event);
+    }
+
+    // read-only view of the machine state for debuggers and tools
+    pub fn pc(& self) -> u16 { self.pc }
+    pub fn ir(& self) -> u16 { self.ir }
+    pub fn sp(& self) -> u16 { self.sp }
+    pub fn opcode(& self) -> u16 { self.opcode }
+    pub fn cycles(& self) -> u32 { self.cycles }
+    pub fn waiting_for_vblank(& self) -> bool { self.waiting_for_vblank }
+    pub fn draw_deferred(& self) -> bool { self.vblank } // until the DXYN runs in the next frame
+    pub fn v(& self) -> &[u8; 16] { &self.v }
+    pub fn stack(& self) -> &[u16; 16] { &self.stack }
+    pub fn memory(& self) -> &[u8] { &self.memory }
+    pub fn delay_timer(& self) -> u8 { self.delay_timer }
+    pub fn sound_timer(& self) -> u8 { self.sound_timer }
+
+    // addresses of the loaded program, end excluded
+    pub fn program_range(& self) -> (u16, u16)
+    {
+        (self.layout.program_start, self.layout.program_start + self.rom_size as u16)
+    }
+
+    // resolution of gfx, frontends should not assume it stays fixed
+    pub fn screen_size(& self) -> (usize, usize)
+    {
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+
+    // true while the buzzer should be sounding
+    pub fn sound_active(& self) -> bool
+    {
+        self.sound_timer > 0
+    }
+
     /*pub fn debug_render(& self)
     {
         // draw
@@ -544,32 +1128,309 @@ impl VM
         println!("");
     }*/
 
-    pub fn load_application(& mut self, filename : &str) -> bool
+    pub fn load_application(& mut self, filename : &str) -> Result<(), String>
     {
-        // open the file
-        let mut file = File::open(filename).expect("file error");
-
-        // get the file size
-        let fsize = file.metadata().unwrap().len();
+        let file = File::open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        self.load_rom_reader(file).map_err(|e| format!("{}: {}", filename, e))
+    }
 
-        // read the file to a buffer
+    pub fn load_rom_reader<R: Read>(& mut self, mut reader: R) -> Result<(), String>
+    {
         let mut buffer = vec![];
-        file.read_to_end(&mut buffer).expect("couldn't read file");
-        drop(file);
+        reader.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+        self.load_rom_bytes(&buffer)
+    }
 
-        // copy the buffer to chip8 memory
-        if (4096 - 512) > fsize
+    // copies the ROM to chip8 memory at the program start
+    pub fn load_rom_bytes(& mut self, data: &[u8]) -> Result<(), String>
+    {
+        let start = self.layout.program_start as usize;
+        if start + data.len() > self.memory.len() - self.layout.reserved
         {
-            for i in 0..fsize
-            {
-                self.memory[(i + 512) as usize] = buffer[i as usize];
-            }
+            return Err(format!("ROM too big for memory ({} bytes, {} available)", data.len(),
+                (self.memory.len() - self.layout.reserved).saturating_sub(start)));
         }
-        else
+
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        self.rom_size = data.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // a VM with `program` loaded at the program start
+    fn load(program: &[u16]) -> VM
+    {
+        let rom: Vec<u8> = program.iter().flat_map(|opcode| vec![(opcode >> 8) as u8, *opcode as u8]).collect();
+        let mut vm = VM::new();
+        vm.load_rom_bytes(&rom).unwrap();
+        vm
+    }
+
+    fn run(vm: & mut VM, cycles: usize)
+    {
+        for _ in 0..cycles
+        {
+            vm.emulate_cycle();
+        }
+    }
+
+    // the registers after running all of `program`
+    fn registers(program: &[u16], quirks: Quirks) -> [u8; 16]
+    {
+        let mut vm = load(program);
+        vm.quirks = quirks;
+        run(&mut vm, program.len());
+        vm.v
+    }
+
+    #[test]
+    fn arithmetic_flags()
+    {
+        let quirks = Quirks::default();
+
+        let v = registers(&[0x61FF, 0x6202, 0x8124], quirks);
+        assert_eq!((v[1], v[0xF]), (0x01, 1));
+        let v = registers(&[0x6110, 0x6230, 0x8125], quirks);
+        assert_eq!((v[1], v[0xF]), (0xE0, 0));
+        let v = registers(&[0x6110, 0x6230, 0x8127], quirks);
+        assert_eq!((v[1], v[0xF]), (0x20, 1));
+    }
+
+    #[test]
+    fn flag_wins_over_result_in_vf()
+    {
+        let quirks = Quirks::default();
+
+        // 8XY4, 8XY5 and 8XY7 with VX = VF keep the flag, not the result
+        assert_eq!(registers(&[0x6F10, 0x6120, 0x8F14], quirks)[0xF], 0);
+        assert_eq!(registers(&[0x6FF0, 0x6120, 0x8F14], quirks)[0xF], 1);
+        assert_eq!(registers(&[0x6F30, 0x6110, 0x8F15], quirks)[0xF], 1);
+        assert_eq!(registers(&[0x6F10, 0x6130, 0x8F15], quirks)[0xF], 0);
+        assert_eq!(registers(&[0x6F10, 0x6130, 0x8F17], quirks)[0xF], 1);
+        assert_eq!(registers(&[0x6F30, 0x6110, 0x8F17], quirks)[0xF], 0);
+
+        // and so do the shifts, of VY and in place
+        let vip = Quirks { shift: false, ..quirks };
+        assert_eq!(registers(&[0x6102, 0x8F16], vip)[0xF], 0);
+        assert_eq!(registers(&[0x6181, 0x8F1E], vip)[0xF], 1);
+        assert_eq!(registers(&[0x6F02, 0x8F06], quirks)[0xF], 0);
+        assert_eq!(registers(&[0x6F81, 0x8F0E], quirks)[0xF], 1);
+    }
+
+    #[test]
+    fn memory_layout()
+    {
+        // the font sprite of the digit in V5
+        let mut vm = load(&[0x6505, 0xF529]);
+        vm.set_layout(MemoryLayout { program_start: 0x600, font_base: 0x050, size: 4096, reserved: 0 }).unwrap();
+        assert_eq!((vm.pc, vm.program_range()), (0x600, (0x600, 0x604)));
+        assert_eq!(vm.memory[0x050..0x050 + FONTSET.len()], FONTSET[..]);
+        run(&mut vm, 2);
+        assert_eq!(vm.ir, 0x050 + 5 * 5);
+
+        // programs can't grow into the reserved area
+        let mut vm = VM::new();
+        vm.set_layout(MemoryLayout { program_start: 0x200, font_base: 0, size: 4096, reserved: 0x160 }).unwrap();
+        assert!(vm.load_rom_bytes(&vec![0; 4096 - 0x200 - 0x160]).is_ok());
+        assert!(vm.load_rom_bytes(&vec![0; 4096 - 0x200 - 0x160 + 1]).is_err());
+
+        // and the font has to fit
+        assert!(vm.set_layout(MemoryLayout { program_start: 0x200, font_base: 0x0FC0, size: 4096, reserved: 0 }).is_err());
+        assert!(vm.set_layout(MemoryLayout { program_start: 0x200, font_base: 0, size: 0x20000, reserved: 0 }).is_err());
+    }
+
+    #[test]
+    fn fonts()
+    {
+        // FX29 and FX30 point into the installed fonts
+        let small: Vec<u8> = (0..80).collect();
+        let big: Vec<u8> = (80..240).collect();
+        let mut vm = load(&[0x6A0A, 0xFA29, 0xFA30]);
+        vm.set_font(&small, &big).unwrap();
+        run(&mut vm, 2);
+        assert_eq!(vm.memory[vm.ir as usize], 50);
+        run(&mut vm, 1);
+        assert_eq!(vm.memory[vm.ir as usize], 180);
+
+        assert!(vm.set_font(&small[..79], &big).is_err());
+    }
+
+    // 0300 calling the 1802 `routine` at 0x300, then a loop
+    fn hybrid(routine: &[u8]) -> VM
+    {
+        let mut rom = vec![0x03, 0x00, 0x12, 0x02];
+        rom.resize(0x100, 0);
+        rom.extend_from_slice(routine);
+
+        let mut vm = VM::new();
+        vm.load_rom_bytes(&rom).unwrap();
+        vm.enable_machine_code();
+        vm
+    }
+
+    #[test]
+    fn machine_code()
+    {
+        // LDI 42, STR R6 (VX of the 0NNN, V3 here), SEP R4
+        let mut vm = hybrid(&[0xF8, 0x42, 0x56, 0xD4]);
+        run(&mut vm, 1);
+        assert_eq!((vm.pc, vm.v[3]), (0x202, 0x42));
+    }
+
+    #[test]
+    fn machine_code_gives_the_frame_back()
+    {
+        // BN3 polling key 0, SEP R4
+        let mut vm = hybrid(&[0x3E, 0x00, 0xD4]);
+        run(&mut vm, 3);
+        assert_eq!(vm.pc, 0x200);
+        assert!(vm.in_machine_code);
+
+        vm.key[0] = 1;
+        run(&mut vm, 1);
+        assert_eq!(vm.pc, 0x202);
+        assert!(!vm.in_machine_code);
+
+        // IDL, SEP R4: resumes at the display interrupt
+        let mut vm = hybrid(&[0x00, 0xD4]);
+        run(&mut vm, 3);
+        assert_eq!(vm.pc, 0x200);
+        vm.vblank();
+        run(&mut vm, 1);
+        assert_eq!(vm.pc, 0x202);
+    }
+
+    // two sprites, then a loop
+    const DRAW_TWICE: [u16; 3] = [0xD015, 0xD015, 0x1204];
+
+    #[test]
+    fn display_wait()
+    {
+        let mut vm = load(&DRAW_TWICE);
+        vm.quirks.display_wait = true;
+
+        // the draw waits for the end of the frame, without drawing
+        run(&mut vm, 3);
+        assert!(vm.waiting_for_vblank() && !vm.draw_deferred());
+        assert_eq!(vm.pc, 0x200);
+        assert!(vm.gfx.iter().all(|&p| p == 0));
+
+        // then runs first thing in the next frame
+        vm.vblank();
+        assert!(!vm.waiting_for_vblank() && vm.draw_deferred());
+        run(&mut vm, 1);
+        assert_eq!(vm.pc, 0x202);
+        assert!(vm.gfx.iter().any(|&p| p != 0));
+        assert!(!vm.draw_deferred());
+
+        // one draw per frame
+        run(&mut vm, 1);
+        assert!(vm.waiting_for_vblank());
+        assert_eq!(vm.pc, 0x202);
+
+        // a frame without a waiting draw doesn't let the next one through
+        let mut vm = load(&[0x6000, 0xD015]);
+        vm.quirks.display_wait = true;
+        run(&mut vm, 1);
+        vm.vblank();
+        run(&mut vm, 1);
+        assert!(vm.waiting_for_vblank());
+    }
+
+    #[test]
+    fn draws_without_display_wait()
+    {
+        let mut vm = load(&DRAW_TWICE);
+        run(&mut vm, 2);
+        assert_eq!(vm.pc, 0x204);
+        assert!(!vm.waiting_for_vblank());
+
+        // VIP timing waits whatever the quirk
+        let mut vm = load(&DRAW_TWICE);
+        vm.timing = Timing::Vip;
+        run(&mut vm, 1);
+        assert!(vm.waiting_for_vblank());
+    }
+
+    #[test]
+    fn key_events()
+    {
+        let mut vm = VM::new();
+        vm.press_key(3);
+        vm.press_key(3);
+        vm.press_key(16);
+        assert_eq!(vm.key[3], 1);
+        vm.release_key(3);
+        vm.release_key(4);
+        assert_eq!(vm.key[3], 0);
+        assert_eq!(vm.key_events, [KeyEvent::Press(3), KeyEvent::Release(3)]);
+
+        // the oldest events go when nothing reads them
+        for _ in 0..KEY_EVENTS
         {
-            panic!("ROM too big for memory");
+            vm.press_key(5);
+            vm.release_key(5);
         }
+        assert_eq!(vm.key_events.len(), KEY_EVENTS);
+    }
 
-        return true;
+    // FX0A into V1, then a loop
+    const WAIT_FOR_KEY: [u16; 2] = [0xF10A, 0x1202];
+
+    #[test]
+    fn wait_for_key()
+    {
+        let mut vm = load(&WAIT_FOR_KEY);
+        run(&mut vm, 3);
+        assert_eq!(vm.pc, 0x200);
+
+        // a key already held counts, also when written directly
+        vm.key[7] = 1;
+        run(&mut vm, 1);
+        assert_eq!((vm.pc, vm.v[1]), (0x202, 7));
+
+        // and so does a tap between two cycles
+        let mut vm = load(&WAIT_FOR_KEY);
+        run(&mut vm, 1);
+        vm.press_key(9);
+        vm.release_key(9);
+        run(&mut vm, 1);
+        assert_eq!((vm.pc, vm.v[1]), (0x202, 9));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn wait_for_key_release()
+    {
+        let mut vm = load(&WAIT_FOR_KEY);
+        vm.quirks.key_release = true;
+
+        // a key held before FX0A doesn't count
+        vm.press_key(2);
+        run(&mut vm, 2);
+        vm.release_key(2);
+        run(&mut vm, 2);
+        assert_eq!(vm.pc, 0x200);
+
+        // a new press waits for the release
+        vm.press_key(4);
+        run(&mut vm, 2);
+        assert_eq!(vm.pc, 0x200);
+        vm.release_key(4);
+        run(&mut vm, 1);
+        assert_eq!((vm.pc, vm.v[1]), (0x202, 4));
+
+        // a tap between two cycles is seen whole
+        let mut vm = load(&WAIT_FOR_KEY);
+        vm.quirks.key_release = true;
+        run(&mut vm, 1);
+        vm.press_key(0xA);
+        vm.release_key(0xA);
+        run(&mut vm, 1);
+        assert_eq!((vm.pc, vm.v[1]), (0x202, 0xA));
+    }
+}
@@ -0,0 +1,231 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use sdl2::VideoSubsystem;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::dale8::VM;
+use crate::disasm;
+use crate::heatmap::{self, Heatmap};
+use crate::osd::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const WINDOW_WIDTH: u32 = 800;
+const WINDOW_HEIGHT: u32 = 560;
+
+// size of the font pixels and of one character cell
+const SCALE: u32 = 2;
+const CELL_WIDTH: i32 = (GLYPH_WIDTH + 1) * SCALE as i32;
+const LINE_HEIGHT: i32 = (GLYPH_HEIGHT + 2) * SCALE as i32;
+const MARGIN: i32 = 8;
+
+// instructions listed before and after pc
+const LISTING_BEFORE: u16 = 8;
+const LISTING_AFTER: u16 = 22;
+
+// bytes per hex dump row, and rows shown around pc and I
+const DUMP_WIDTH: u16 = 8;
+const DUMP_ROWS: u16 = 8;
+
+// size of one address in the heatmap panel
+const HEATMAP_SCALE: u32 = 3;
+
+const BACKGROUND: Color = Color { r: 0x10, g: 0x10, b: 0x18, a: 0xFF };
+const TEXT: Color = Color { r: 0xC0, g: 0xC0, b: 0xC0, a: 0xFF };
+const TITLE: Color = Color { r: 0x70, g: 0xA0, b: 0xFF, a: 0xFF };
+const HIGHLIGHT: Color = Color { r: 0xFF, g: 0xD0, b: 0x40, a: 0xFF };
+const HIGHLIGHT_BAR: Color = Color { r: 0x30, g: 0x30, b: 0x48, a: 0xFF };
+const DIM: Color = Color { r: 0x50, g: 0x50, b: 0x58, a: 0xFF };
+
+// second window showing the VM state, redrawn every frame
+pub struct DebugView
+{
+    canvas: Canvas<Window>,
+    heatmap: Heatmap,
+}
+
+impl DebugView
+{
+    pub fn new(video: &VideoSubsystem) -> Result<DebugView, String>
+    {
+        let window = video.window("dale8 debugger", WINDOW_WIDTH, WINDOW_HEIGHT).resizable().build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        Ok(DebugView { canvas, heatmap: Heatmap::new() })
+    }
+
+    pub fn window_id(&self) -> u32
+    {
+        self.canvas.window().id()
+    }
+
+    // call after every emulated frame, so the heatmap decays at the
+    // emulation speed
+    pub fn update(&mut self, vm: &mut VM)
+    {
+        self.heatmap.update(vm);
+    }
+
+    pub fn draw(&mut self, vm: &VM)
+    {
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+
+        self.draw_registers(vm, MARGIN, MARGIN);
+        self.draw_heatmap(MARGIN, MARGIN + 21 * LINE_HEIGHT);
+        self.draw_listing(vm, MARGIN + 30 * CELL_WIDTH, MARGIN);
+        self.draw_memory(vm, MARGIN + 54 * CELL_WIDTH, MARGIN);
+
+        self.canvas.present();
+    }
+
+    fn text(&mut self, column: i32, line: i32, x: i32, y: i32, text: &str, color: Color)
+    {
+        osd::draw_text(&mut self.canvas, x + column * CELL_WIDTH, y + line * LINE_HEIGHT, SCALE, text, color);
+    }
+
+    fn draw_registers(&mut self, vm: &VM, x: i32, y: i32)
+    {
+        self.text(0, 0, x, y, "REGISTERS", TITLE);
+        self.text(0, 1, x, y, &format!("PC {:03X}  I {:03X}  SP {:X}", vm.pc(), vm.ir(), vm.sp()), TEXT);
+        self.text(0, 2, x, y, &format!("OP {:04X}  DT {:02X}  ST {:02X}", vm.opcode(), vm.delay_timer(), vm.sound_timer()), TEXT);
+        // the frame has ended by the time this is drawn, the DXYN runs in
+        // the next one
+        if vm.draw_deferred()
+        {
+            self.text(0, 3, x, y, "DRAW DEFERRED TO NEXT FRAME", HIGHLIGHT);
+        }
+
+        for (i, value) in vm.v().iter().enumerate()
+        {
+            let (column, line) = ((i % 4) as i32 * 7, 4 + (i / 4) as i32);
+            self.text(column, line, x, y, &format!("V{:X} {:02X}", i, value), TEXT);
+        }
+
+        // the stack grows upwards, the entries above sp are stale
+        self.text(0, 9, x, y, "STACK", TITLE);
+        for (i, address) in vm.stack().iter().enumerate()
+        {
+            let (column, line) = ((i % 4) as i32 * 7, 10 + (i / 4) as i32);
+            let color = if (i as u16) < vm.sp() { TEXT } else { DIM };
+            self.text(column, line, x, y, &format!("{:X} {:03X}", i, address), color);
+        }
+
+        // hex keypad layout, pressed keys highlighted
+        self.text(0, 15, x, y, "KEYS", TITLE);
+        let layout = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+        for (row, keys) in layout.iter().enumerate()
+        {
+            for (column, &key) in keys.iter().enumerate()
+            {
+                let color = if vm.key[key] != 0 { HIGHLIGHT } else { DIM };
+                self.text(column as i32 * 2, 16 + row as i32, x, y, &format!("{:X}", key), color);
+            }
+        }
+    }
+
+    // one square per address, rows of 64 bytes
+    fn draw_heatmap(&mut self, x: i32, y: i32)
+    {
+        self.text(0, 0, x, y, "HEATMAP", TITLE);
+        self.text(8, 0, x, y, "W", Color::RGB(0xFF, 0x40, 0x40));
+        self.text(10, 0, x, y, "X", Color::RGB(0x40, 0xFF, 0x40));
+        self.text(12, 0, x, y, "R", Color::RGB(0x60, 0x60, 0xFF));
+
+        let top = y + LINE_HEIGHT;
+        let size = HEATMAP_SCALE as i32;
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.fill_rect(Rect::new(x, top, heatmap::SIZE as u32 * HEATMAP_SCALE, heatmap::SIZE as u32 * HEATMAP_SCALE)).ok();
+
+        for address in 0..heatmap::SIZE * heatmap::SIZE
+        {
+            let [r, g, b] = self.heatmap.color(address);
+            if r | g | b != 0
+            {
+                let (column, row) = ((address % heatmap::SIZE) as i32, (address / heatmap::SIZE) as i32);
+                self.canvas.set_draw_color(Color::RGB(r, g, b));
+                self.canvas.fill_rect(Rect::new(x + column * size, top + row * size, HEATMAP_SCALE, HEATMAP_SCALE)).ok();
+            }
+        }
+    }
+
+    fn draw_listing(&mut self, vm: &VM, x: i32, y: i32)
+    {
+        self.text(0, 0, x, y, "DISASSEMBLY", TITLE);
+
+        let memory = vm.memory();
+        let pc = vm.pc();
+        let start = pc.saturating_sub(LISTING_BEFORE * 2);
+
+        for (line, address) in (start..=pc + LISTING_AFTER * 2).step_by(2).enumerate()
+        {
+            let line = line as i32 + 1;
+            if address as usize + 1 >= memory.len()
+            {
+                break;
+            }
+
+            let opcode = (memory[address as usize] as u16) << 8 | memory[address as usize + 1] as u16;
+            let color = if address == pc
+            {
+                self.canvas.set_draw_color(HIGHLIGHT_BAR);
+                self.canvas.fill_rect(Rect::new(x - SCALE as i32, y + line * LINE_HEIGHT - SCALE as i32,
+                    22 * CELL_WIDTH as u32, LINE_HEIGHT as u32)).ok();
+                HIGHLIGHT
+            }
+            else
+            {
+                TEXT
+            };
+
+            self.text(0, line, x, y, &format!("{:03X} {:04X} {}", address, opcode, disasm::disassemble(opcode)), color);
+        }
+    }
+
+    fn draw_memory(&mut self, vm: &VM, x: i32, y: i32)
+    {
+        self.text(0, 0, x, y, "MEMORY AT PC", TITLE);
+        self.draw_dump(vm, vm.pc(), x, y + LINE_HEIGHT);
+
+        let top = y + (DUMP_ROWS as i32 + 2) * LINE_HEIGHT;
+        self.text(0, 0, x, top, "MEMORY AT I", TITLE);
+        self.draw_dump(vm, vm.ir(), x, top + LINE_HEIGHT);
+    }
+
+    // rows around `address`, the byte at `address` highlighted
+    fn draw_dump(&mut self, vm: &VM, address: u16, x: i32, y: i32)
+    {
+        let memory = vm.memory();
+        let last_row = ((memory.len() - 1) as u16) / DUMP_WIDTH * DUMP_WIDTH;
+        let first = (address / DUMP_WIDTH * DUMP_WIDTH).saturating_sub(DUMP_WIDTH * 2).min(last_row.saturating_sub(DUMP_WIDTH * (DUMP_ROWS - 1)));
+
+        for row in 0..DUMP_ROWS
+        {
+            let start = first + row * DUMP_WIDTH;
+            self.text(0, row as i32, x, y, &format!("{:03X}", start), DIM);
+
+            for i in 0..DUMP_WIDTH
+            {
+                let at = start + i;
+                if at as usize >= memory.len()
+                {
+                    break;
+                }
+                let color = if at == address { HIGHLIGHT } else { TEXT };
+                self.text(4 + i as i32 * 3, row as i32, x, y, &format!("{:02X}", memory[at as usize]), color);
+            }
+        }
+    }
+}
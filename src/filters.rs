@@ -0,0 +1,217 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::config;
+
+// RGB24 image without row padding
+#[derive(Clone)]
+pub struct Image
+{
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Image
+{
+    pub fn new(width: usize, height: usize) -> Image
+    {
+        Image { width, height, data: vec![0; width * height * 3] }
+    }
+
+    pub fn pitch(&self) -> usize
+    {
+        self.width * 3
+    }
+
+    // nearest-neighbour enlargement by a whole factor
+    pub fn upscale(&self, factor: usize, out: &mut Image)
+    {
+        if out.width != self.width * factor || out.height != self.height * factor
+        {
+            *out = Image::new(self.width * factor, self.height * factor);
+        }
+
+        for y in 0..out.height
+        {
+            let src_row = &self.data[(y / factor) * self.pitch()..];
+            let dst_row = &mut out.data[y * out.width * 3..(y + 1) * out.width * 3];
+            for (x, dst) in dst_row.chunks_mut(3).enumerate()
+            {
+                let offset = (x / factor) * 3;
+                dst.copy_from_slice(&src_row[offset..offset + 3]);
+            }
+        }
+    }
+}
+
+// CRT-style effects applied in order to the upscaled image, configured as
+//
+//   [display]
+//   filters = scanlines, bloom, curvature
+//   filter_scale = 4
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter
+{
+    Scanlines, // darkens the bottom row of every source pixel
+    Grid,      // darkens the edges of every source pixel
+    Bloom,     // lets lit pixels glow into their neighbours
+    Curvature, // barrel distortion of a curved tube
+}
+
+// how much the scanline and grid filters darken
+const SCANLINE_LEVEL: u32 = 128;
+const GRID_LEVEL: u32 = 160;
+
+// strength of the bloom glow (out of 256) and of the barrel distortion
+const BLOOM_LEVEL: u32 = 128;
+const CURVATURE: f32 = 0.08;
+
+pub fn parse(text: &str) -> Result<Vec<Filter>, String>
+{
+    config::list(text).iter().map(|name|
+    {
+        match *name
+        {
+            "scanlines" => Ok(Filter::Scanlines),
+            "grid" => Ok(Filter::Grid),
+            "bloom" => Ok(Filter::Bloom),
+            "curvature" => Ok(Filter::Curvature),
+            _ => Err(format!("unknown filter {}", name)),
+        }
+    }).collect()
+}
+
+// `cell` is the size of one source pixel in the upscaled image
+pub fn apply(filters: &[Filter], cell: usize, image: &mut Image)
+{
+    for filter in filters
+    {
+        match filter
+        {
+            Filter::Scanlines => scanlines(cell, image),
+            Filter::Grid => grid(cell, image),
+            Filter::Bloom => bloom(cell, image),
+            Filter::Curvature => curvature(image),
+        }
+    }
+}
+
+fn scanlines(cell: usize, image: &mut Image)
+{
+    if cell < 2
+    {
+        return;
+    }
+
+    let pitch = image.pitch();
+    for (y, row) in image.data.chunks_mut(pitch).enumerate()
+    {
+        if y % cell == cell - 1
+        {
+            row.iter_mut().for_each(|c| *c = (*c as u32 * SCANLINE_LEVEL / 256) as u8);
+        }
+    }
+}
+
+fn grid(cell: usize, image: &mut Image)
+{
+    if cell < 3
+    {
+        return;
+    }
+
+    let pitch = image.pitch();
+    for (y, row) in image.data.chunks_mut(pitch).enumerate()
+    {
+        for (x, pixel) in row.chunks_mut(3).enumerate()
+        {
+            if y % cell == cell - 1 || x % cell == cell - 1
+            {
+                pixel.iter_mut().for_each(|c| *c = (*c as u32 * GRID_LEVEL / 256) as u8);
+            }
+        }
+    }
+}
+
+// adds a box-blurred copy of the image on top of itself
+fn bloom(cell: usize, image: &mut Image)
+{
+    let radius = (cell / 2).max(1);
+    let (w, h) = (image.width, image.height);
+
+    let horizontal = blur(&image.data, w, h, radius, 3, w * 3);
+    let blurred = blur(&horizontal, h, w, radius, w * 3, 3);
+
+    for (c, b) in image.data.iter_mut().zip(blurred)
+    {
+        *c = (*c as u32 + b as u32 * BLOOM_LEVEL / 256).min(255) as u8;
+    }
+}
+
+// one-dimensional box blur along lines of `len` pixels, `step` bytes apart,
+// with consecutive lines `stride` bytes apart
+fn blur(data: &[u8], len: usize, lines: usize, radius: usize, step: usize, stride: usize) -> Vec<u8>
+{
+    let mut out = vec![0; data.len()];
+    let span = (radius * 2 + 1) as u32;
+
+    for line in 0..lines
+    {
+        for channel in 0..3
+        {
+            let at = |i: usize| data[line * stride + i * step + channel] as u32;
+
+            // running sum over the window, clamped at the borders
+            let mut sum: u32 = (0..=radius * 2).map(|i| at(i.saturating_sub(radius).min(len - 1))).sum();
+            for i in 0..len
+            {
+                out[line * stride + i * step + channel] = (sum / span) as u8;
+                sum -= at(i.saturating_sub(radius));
+                sum += at((i + radius + 1).min(len - 1));
+            }
+        }
+    }
+    out
+}
+
+fn curvature(image: &mut Image)
+{
+    let source = image.data.clone();
+    let (w, h) = (image.width as f32, image.height as f32);
+    let pitch = image.pitch();
+
+    for y in 0..image.height
+    {
+        for x in 0..image.width
+        {
+            // -1..1 across the screen, pushed outwards the further from the center
+            let u = (x as f32 + 0.5) / w * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / h * 2.0 - 1.0;
+            let su = u * (1.0 + CURVATURE * v * v);
+            let sv = v * (1.0 + CURVATURE * u * u);
+
+            let offset = y * pitch + x * 3;
+            if su.abs() > 1.0 || sv.abs() > 1.0
+            {
+                image.data[offset..offset + 3].copy_from_slice(&[0, 0, 0]);
+                continue;
+            }
+
+            let sx = (((su + 1.0) / 2.0 * w) as usize).min(image.width - 1);
+            let sy = (((sv + 1.0) / 2.0 * h) as usize).min(image.height - 1);
+            let src = sy * pitch + sx * 3;
+            image.data[offset..offset + 3].copy_from_slice(&source[src..src + 3]);
+        }
+    }
+}
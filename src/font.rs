@@ -0,0 +1,202 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::fs;
+
+use crate::config::Config;
+use crate::dale8::{BIG_FONTSET, FONTSET};
+use crate::options::Options;
+use crate::scanner::Platform;
+
+// the 4x5 digits of the interpreters, as they shipped
+pub const SMALL_FONTS: [(&str, &[u8; 80]); 5] =
+[
+    ("chip48", &FONTSET),
+    ("vip", &VIP),
+    ("dream6800", &DREAM6800),
+    ("eti660", &ETI660),
+    ("fishnchips", &FISH_N_CHIPS),
+];
+
+pub const BIG_FONTS: [(&str, &[u8; 160]); 2] =
+[
+    ("schip", &SCHIP_BIG),
+    ("xo-chip", &BIG_FONTSET),
+];
+
+const VIP: [u8; 80] =
+[
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x60, 0x20, 0x20, 0x20, 0x70, // 0 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0xF0, 0x10, 0xF0, // 2 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, 0xF0, 0x80, 0xF0, 0x10, 0xF0, // 4 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x10, 0x10, 0x10, // 6 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, // 8 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x50, 0x70, 0x50, 0xF0, // A B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, 0xF0, 0x50, 0x50, 0x50, 0xF0, // C D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80, // E F
+];
+
+const DREAM6800: [u8; 80] =
+[
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x40, 0x40, 0x40, 0x40, 0x40, // 0 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20, 0xE0, 0x20, 0xE0, // 2 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, // 4 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20, // 6 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 8 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // A B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, 0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // C D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80, // E F
+];
+
+const ETI660: [u8; 80] =
+[
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0x20, // 0 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20, 0xE0, 0x20, 0xE0, // 2 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, // 4 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20, // 6 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 8 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0x80, 0x80, 0xE0, 0xA0, 0xE0, // A B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, 0x20, 0x20, 0xE0, 0xA0, 0xE0, // C D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80, // E F
+];
+
+const FISH_N_CHIPS: [u8; 80] =
+[
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, // 0 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0x70, 0x10, 0xF0, // 2 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, // 4 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, // 6 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, // 8 9
+    0x60, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, // A B
+    0x70, 0x80, 0x80, 0x80, 0x70, 0xE0, 0x90, 0x90, 0x90, 0xE0, // C D
+    0xF0, 0x80, 0xE0, 0x80, 0xF0, 0xF0, 0x80, 0xE0, 0x80, 0x80, // E F
+];
+
+// SCHIP 1.1 only has the digits 0-9
+const SCHIP_BIG: [u8; 160] =
+[
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+// the small and big font to install:
+//
+//   [font]
+//   small = vip
+//   big = schip
+//   file = digits.bin
+//
+// --font takes either a small font name or a file. font files hold the 80
+// bytes of the small font, optionally followed by the 160 of the big one.
+// both fonts default to the ones of the platform
+pub fn fonts(config: &Config, options: &Options, platform: Option<Platform>) -> Result<(Vec<u8>, Vec<u8>), String>
+{
+    let mut small = match platform
+    {
+        Some(Platform::Vip) => VIP.to_vec(),
+        _ => FONTSET.to_vec(),
+    };
+    let mut big = match platform
+    {
+        Some(Platform::Schip) => SCHIP_BIG.to_vec(),
+        _ => BIG_FONTSET.to_vec(),
+    };
+
+    if let Some(name) = config.get("font", "small")
+    {
+        small = find(&SMALL_FONTS, name)?.to_vec();
+    }
+    if let Some(name) = config.get("font", "big")
+    {
+        big = find(&BIG_FONTS, name)?.to_vec();
+    }
+
+    let file = match options.font
+    {
+        Some(ref font) if SMALL_FONTS.iter().any(|f| f.0 == font) =>
+        {
+            small = find(&SMALL_FONTS, font)?.to_vec();
+            None
+        },
+        Some(ref font) => Some(font.as_str()),
+        None => config.get("font", "file"),
+    };
+
+    if let Some(filename) = file
+    {
+        let data = fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        match data.len()
+        {
+            80 => small = data,
+            240 =>
+            {
+                small = data[..80].to_vec();
+                big = data[80..].to_vec();
+            },
+            size => return Err(format!("{}: expected 80 or 240 bytes, found {}", filename, size)),
+        }
+    }
+
+    Ok((small, big))
+}
+
+fn find<T: ?Sized>(fonts: &[(&str, &'static T)], name: &str) -> Result<&'static T, String>
+{
+    fonts.iter().find(|f| f.0 == name).map(|f| f.1)
+        .ok_or(format!("unknown font {}, expected one of {}", name, fonts.iter().map(|f| f.0).collect::<Vec<&str>>().join(", ")))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::options;
+
+    fn fonts_for(args: &[&str], config: &str, platform: Option<Platform>) -> Result<(Vec<u8>, Vec<u8>), String>
+    {
+        let args: Vec<String> = ["dale8"].iter().chain(args).chain(&["rom"]).map(|a| a.to_string()).collect();
+        fonts(&Config::parse(config)?, &options::parse(&args)?, platform)
+    }
+
+    #[test]
+    fn platform_defaults()
+    {
+        assert_eq!(fonts_for(&[], "", None), Ok((FONTSET.to_vec(), BIG_FONTSET.to_vec())));
+        assert_eq!(fonts_for(&[], "", Some(Platform::Vip)), Ok((VIP.to_vec(), BIG_FONTSET.to_vec())));
+        assert_eq!(fonts_for(&[], "", Some(Platform::Schip)), Ok((FONTSET.to_vec(), SCHIP_BIG.to_vec())));
+    }
+
+    #[test]
+    fn selected_fonts()
+    {
+        let (small, big) = fonts_for(&[], "[font]\nsmall = dream6800\nbig = schip", None).unwrap();
+        assert_eq!((small, big), (DREAM6800.to_vec(), SCHIP_BIG.to_vec()));
+
+        // --font names a small font or a file
+        let (small, _) = fonts_for(&["--font", "eti660"], "[font]\nsmall = vip", None).unwrap();
+        assert_eq!(small, ETI660.to_vec());
+        assert!(fonts_for(&["--font", "no such font file"], "", None).is_err());
+        assert!(fonts_for(&[], "[font]\nbig = vip", None).is_err());
+    }
+}
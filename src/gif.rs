@@ -0,0 +1,288 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+// GIF decoder returning the colour indices of every frame, row by row,
+// which is where Octo cartridges keep their data
+
+const MAX_CODE_SIZE: u32 = 12;
+
+pub fn is_gif(data: &[u8]) -> bool
+{
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+struct Reader<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    fn byte(&mut self) -> Result<u8, String>
+    {
+        let byte = *self.data.get(self.pos).ok_or("truncated GIF")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, String>
+    {
+        Ok(self.byte()? as u16 | (self.byte()? as u16) << 8)
+    }
+
+    fn skip(&mut self, count: usize)
+    {
+        self.pos += count;
+    }
+
+    // data sub-blocks up to the empty terminator
+    fn blocks(&mut self) -> Result<Vec<u8>, String>
+    {
+        let mut data = Vec::new();
+        loop
+        {
+            let size = self.byte()? as usize;
+            if size == 0
+            {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.data.get(self.pos..self.pos + size).ok_or("truncated GIF")?);
+            self.pos += size;
+        }
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<Vec<u8>>, String>
+{
+    if !is_gif(data)
+    {
+        return Err("not a GIF image".to_string());
+    }
+
+    let mut reader = Reader { data, pos: 6 };
+    reader.skip(4); // logical screen size
+    let flags = reader.byte()?;
+    reader.skip(2); // background colour and aspect ratio
+    if flags & 0x80 != 0
+    {
+        reader.skip(3 << ((flags & 7) + 1));
+    }
+
+    let mut frames = Vec::new();
+    loop
+    {
+        match reader.byte()?
+        {
+            // extension: graphic control, comment, application...
+            0x21 =>
+            {
+                reader.byte()?;
+                reader.blocks()?;
+            },
+
+            // image descriptor
+            0x2C =>
+            {
+                reader.skip(4); // position
+                let width = reader.u16()? as usize;
+                let height = reader.u16()? as usize;
+                let flags = reader.byte()?;
+                if flags & 0x80 != 0
+                {
+                    reader.skip(3 << ((flags & 7) + 1));
+                }
+
+                let code_size = reader.byte()? as u32;
+                let mut pixels = lzw(&reader.blocks()?, code_size, width * height)?;
+                pixels.resize(width * height, 0);
+                if flags & 0x40 != 0 && width > 0
+                {
+                    pixels = deinterlace(&pixels, width, height);
+                }
+                frames.push(pixels);
+            },
+
+            // trailer
+            0x3B => return Ok(frames),
+
+            _ => return Err("invalid GIF block".to_string()),
+        }
+    }
+}
+
+fn lzw(data: &[u8], min_code_size: u32, size: usize) -> Result<Vec<u8>, String>
+{
+    if !(2..=8).contains(&min_code_size)
+    {
+        return Err("invalid LZW code size".to_string());
+    }
+
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    // every code is a previous code followed by one byte
+    let mut prefixes: Vec<u16> = Vec::with_capacity(4096);
+    let mut suffixes: Vec<u8> = Vec::with_capacity(4096);
+    let reset = |prefixes: &mut Vec<u16>, suffixes: &mut Vec<u8>|
+    {
+        prefixes.clear();
+        suffixes.clear();
+        for i in 0..clear + 2
+        {
+            prefixes.push(u16::MAX);
+            suffixes.push(i as u8);
+        }
+    };
+    reset(&mut prefixes, &mut suffixes);
+
+    let mut out = Vec::with_capacity(size);
+    let mut code_size = min_code_size + 1;
+    let mut previous: Option<u16> = None;
+    let (mut bits, mut count, mut pos) = (0u32, 0u32, 0usize);
+    let mut string = Vec::new();
+
+    loop
+    {
+        while count < code_size
+        {
+            match data.get(pos)
+            {
+                Some(&byte) => bits |= (byte as u32) << count,
+                None => return Ok(out), // some encoders leave out the end code
+            }
+            pos += 1;
+            count += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as u16;
+        bits >>= code_size;
+        count -= code_size;
+
+        if code == clear
+        {
+            reset(&mut prefixes, &mut suffixes);
+            code_size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end
+        {
+            return Ok(out);
+        }
+
+        let next = prefixes.len() as u16;
+        let last = match previous
+        {
+            Some(last) => last,
+            None =>
+            {
+                if code >= next
+                {
+                    return Err("invalid LZW code".to_string());
+                }
+                out.push(suffixes[code as usize]);
+                previous = Some(code);
+                continue;
+            },
+        };
+
+        // a code not in the table yet is the previous string plus its own
+        // first byte
+        let first = match code
+        {
+            _ if code < next => first_byte(&prefixes, &suffixes, code),
+            _ if code == next => first_byte(&prefixes, &suffixes, last),
+            _ => return Err("invalid LZW code".to_string()),
+        };
+
+        if next < 4096
+        {
+            prefixes.push(last);
+            suffixes.push(first);
+            if prefixes.len() == 1 << code_size && code_size < MAX_CODE_SIZE
+            {
+                code_size += 1;
+            }
+        }
+
+        // the string of the code, built backwards
+        string.clear();
+        let mut at = code;
+        while at != u16::MAX
+        {
+            string.push(suffixes[at as usize]);
+            at = prefixes[at as usize];
+        }
+        out.extend(string.iter().rev());
+        previous = Some(code);
+    }
+}
+
+fn first_byte(prefixes: &[u16], suffixes: &[u8], mut code: u16) -> u8
+{
+    while prefixes[code as usize] != u16::MAX
+    {
+        code = prefixes[code as usize];
+    }
+    suffixes[code as usize]
+}
+
+// interlaced images store rows 0, 8, 16... then 4, 12... then 2, 6... then
+// the odd rows
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8>
+{
+    let mut out = vec![0; pixels.len()];
+    let mut rows = pixels.chunks(width);
+    for &(start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)].iter()
+    {
+        for y in (start..height).step_by(step)
+        {
+            if let Some(row) = rows.next()
+            {
+                out[y * width..y * width + row.len()].copy_from_slice(row);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // 4x4 with a four colour global table. the LZW codes grow from 3 to 4
+    // bits halfway through the image
+    const IMAGE: [u8; 46] =
+    [
+        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x04, 0x00, 0x04, 0x00, 0x81, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF,
+        0x2C, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x00,
+        0x02, 0x07, 0x44, 0x62, 0x22, 0xA7, 0x78, 0x53, 0x00, 0x00,
+        0x3B,
+    ];
+
+    #[test]
+    fn lzw_image()
+    {
+        let frames = decode(&IMAGE).unwrap();
+        assert_eq!(frames, vec![vec![0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 3]]);
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(decode(b"PNG").is_err());
+        assert!(decode(&IMAGE[..40]).is_err());
+    }
+}
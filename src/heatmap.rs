@@ -0,0 +1,98 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::dale8::{AccessCounts, VM};
+use crate::filters::Image;
+use crate::png;
+
+// the 4 KiB of memory laid out as a 64x64 square, one pixel per address
+pub const SIZE: usize = 64;
+
+// brightness kept per frame, and brightness added by one access
+const DECAY: f32 = 0.95;
+const GAIN: f32 = 0.25;
+
+// upscaling of the exported PNG
+const EXPORT_SCALE: usize = 4;
+
+// recent memory activity: writes in red, executes in green, reads in blue.
+// addresses light up when accessed and fade out over the following frames
+pub struct Heatmap
+{
+    levels: Vec<[f32; 3]>,
+    last: Option<(Vec<u32>, Vec<u32>, Vec<u32>)>,
+}
+
+impl Heatmap
+{
+    pub fn new() -> Heatmap
+    {
+        Heatmap { levels: vec![[0.0; 3]; SIZE * SIZE], last: None }
+    }
+
+    // call once per frame, starts the counting on the VM if needed
+    pub fn update(&mut self, vm: &mut VM)
+    {
+        vm.enable_access_counts();
+        let counts: &AccessCounts = match vm.access_counts()
+        {
+            Some(counts) => counts,
+            None => return,
+        };
+
+        let (reads, writes, executes) = self.last.get_or_insert_with(||
+            (counts.reads.clone(), counts.writes.clone(), counts.executes.clone()));
+
+        for (address, level) in self.levels.iter_mut().enumerate().take(counts.reads.len())
+        {
+            let deltas =
+            [
+                counts.writes[address].wrapping_sub(writes[address]),
+                counts.executes[address].wrapping_sub(executes[address]),
+                counts.reads[address].wrapping_sub(reads[address]),
+            ];
+            for (intensity, &delta) in level.iter_mut().zip(deltas.iter())
+            {
+                *intensity = (*intensity * DECAY + delta as f32 * GAIN).min(1.0);
+            }
+        }
+
+        reads.copy_from_slice(&counts.reads);
+        writes.copy_from_slice(&counts.writes);
+        executes.copy_from_slice(&counts.executes);
+    }
+
+    // colour of one address
+    pub fn color(&self, address: usize) -> [u8; 3]
+    {
+        let level = &self.levels[address];
+        [(level[0] * 255.0) as u8, (level[1] * 255.0) as u8, (level[2] * 255.0) as u8]
+    }
+
+    pub fn image(&self) -> Image
+    {
+        let mut image = Image::new(SIZE, SIZE);
+        for address in 0..SIZE * SIZE
+        {
+            image.data[address * 3..address * 3 + 3].copy_from_slice(&self.color(address));
+        }
+        image
+    }
+
+    pub fn export(&self, filename: &str) -> Result<(), String>
+    {
+        let mut image = Image::new(0, 0);
+        self.image().upscale(EXPORT_SCALE, &mut image);
+        png::write(filename, image.width, image.height, &image.data).map_err(|e| format!("{}: {}", filename, e))
+    }
+}
@@ -0,0 +1,293 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+// deflate decompression (RFC 1951), for ROMs packed in zip archives
+
+const MAX_BITS: usize = 15;
+
+// base lengths and extra bits of the length codes 257..285
+const LENGTH_BASE: [u16; 29] =
+[
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+// base distances and extra bits of the distance codes 0..29
+const DISTANCE_BASE: [u16; 30] =
+[
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// order in which the code length code lengths are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> Bits<'a>
+{
+    fn read(&mut self, count: u32) -> Result<u32, String>
+    {
+        let mut value = 0;
+        for i in 0..count
+        {
+            let byte = *self.data.get(self.pos).ok_or("truncated deflate stream")?;
+            value |= ((byte as u32 >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8
+            {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self)
+    {
+        if self.bit != 0
+        {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// canonical Huffman code: how many codes have each length, and the symbols
+// ordered by code
+struct Huffman
+{
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman
+{
+    fn new(lengths: &[u8]) -> Huffman
+    {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths
+        {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for i in 1..=MAX_BITS
+        {
+            offsets[i + 1] = offsets[i] + counts[i];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate()
+        {
+            if length != 0
+            {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String>
+    {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS
+        {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count
+            {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String>
+{
+    let mut bits = Bits { data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+
+    loop
+    {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)?
+        {
+            0 => stored(&mut bits, &mut out)?,
+            1 =>
+            {
+                let mut lengths = [0u8; 288];
+                for (symbol, length) in lengths.iter_mut().enumerate()
+                {
+                    *length = match symbol { 0..=143 => 8, 144..=255 => 9, 256..=279 => 7, _ => 8 };
+                }
+                codes(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 =>
+            {
+                let (literals, distances) = dynamic(&mut bits)?;
+                codes(&mut bits, &mut out, &literals, &distances)?;
+            },
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+
+        if last
+        {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>) -> Result<(), String>
+{
+    bits.align();
+    let header = bits.data.get(bits.pos..bits.pos + 4).ok_or("truncated deflate stream")?;
+    let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+    if length != !u16::from_le_bytes([header[2], header[3]]) as usize
+    {
+        return Err("invalid stored block length".to_string());
+    }
+
+    bits.pos += 4;
+    out.extend_from_slice(bits.data.get(bits.pos..bits.pos + length).ok_or("truncated deflate stream")?);
+    bits.pos += length;
+    Ok(())
+}
+
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman), String>
+{
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_count = bits.read(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_count)
+    {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    // literal/length and distance code lengths form one sequence
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count
+    {
+        let symbol = code_lengths.decode(bits)?;
+        let (value, repeat) = match symbol
+        {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("repeat without a previous length")?, 3 + bits.read(2)?),
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        for _ in 0..repeat
+        {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count
+    {
+        return Err("too many code lengths".to_string());
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String>
+{
+    loop
+    {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol
+        {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 =>
+            {
+                let index = symbol - 257;
+                let length = LENGTH_BASE[index] as usize + bits.read(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(bits)? as usize;
+                if index >= DISTANCE_BASE.len()
+                {
+                    return Err("invalid distance code".to_string());
+                }
+                let distance = DISTANCE_BASE[index] as usize + bits.read(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > out.len()
+                {
+                    return Err("distance too far back".to_string());
+                }
+
+                // the copy may overlap what it produces
+                let start = out.len() - distance;
+                for i in 0..length
+                {
+                    out.push(out[start + i]);
+                }
+            },
+            _ => return Err("invalid literal/length code".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const STORED: [u8; 10] = [0x01, 0x05, 0x00, 0xFA, 0xFF, b'h', b'e', b'l', b'l', b'o'];
+
+    // "hello " as literals, then one match of length 11 at distance 6
+    const FIXED: [u8; 10] = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x00];
+
+    #[test]
+    fn stored_block()
+    {
+        assert_eq!(inflate(&STORED).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn fixed_huffman_block()
+    {
+        assert_eq!(inflate(&FIXED).unwrap(), b"hello hello hello");
+    }
+
+    #[test]
+    fn several_blocks()
+    {
+        // the stored block without its BFINAL bit, then the fixed one
+        let mut data = STORED.to_vec();
+        data[0] = 0x00;
+        data.extend_from_slice(&FIXED);
+        assert_eq!(inflate(&data).unwrap(), b"hellohello hello hello");
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(inflate(&[0x01, 0x05, 0x00, 0xFA, 0xFE, b'h']).is_err());
+        assert!(inflate(&STORED[..7]).is_err());
+        assert!(inflate(&[0x07]).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+// minimal JSON reader, enough for ROM metadata. objects keep the order of
+// their members
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json
+{
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json
+{
+    pub fn parse(text: &str) -> Result<Json, String>
+    {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos < parser.text.len()
+        {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // member of an object, None for anything else
+    pub fn get(&self, name: &str) -> Option<&Json>
+    {
+        match self
+        {
+            Json::Object(members) => members.iter().find(|m| m.0 == name).map(|m| &m.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str>
+    {
+        match self
+        {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64>
+    {
+        match self
+        {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool>
+    {
+        match self
+        {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]>
+    {
+        match self
+        {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]>
+    {
+        match self
+        {
+            Json::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a>
+{
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn error(&self, message: &str) -> String
+    {
+        let line = self.text[..self.pos.min(self.text.len())].iter().filter(|&&c| c == b'\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+
+    fn whitespace(&mut self)
+    {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace()
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8>
+    {
+        self.whitespace();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String>
+    {
+        if self.peek() == Some(c)
+        {
+            self.pos += 1;
+            Ok(())
+        }
+        else
+        {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String>
+    {
+        if self.text[self.pos..].starts_with(word.as_bytes())
+        {
+            self.pos += word.len();
+            Ok(value)
+        }
+        else
+        {
+            Err(self.error("invalid value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String>
+    {
+        match self.peek()
+        {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(c) if c == b'-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("invalid value")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String>
+    {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        if self.peek() == Some(b'}')
+        {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop
+        {
+            if self.peek() != Some(b'"')
+            {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+
+            match self.peek()
+            {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => { self.pos += 1; return Ok(Json::Object(members)); },
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String>
+    {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']')
+        {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+
+        loop
+        {
+            items.push(self.value()?);
+            match self.peek()
+            {
+                Some(b',') => self.pos += 1,
+                Some(b']') => { self.pos += 1; return Ok(Json::Array(items)); },
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String>
+    {
+        let start = self.pos;
+        while self.pos < self.text.len() && b"+-0123456789.eE".contains(&self.text[self.pos])
+        {
+            self.pos += 1;
+        }
+        let text = String::from_utf8_lossy(&self.text[start..self.pos]);
+        text.parse().map(Json::Number).map_err(|_| self.error(&format!("invalid number {}", text)))
+    }
+
+    fn string(&mut self) -> Result<String, String>
+    {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop
+        {
+            let c = *self.text.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match c
+            {
+                b'"' => break,
+                b'\\' =>
+                {
+                    let escape = *self.text.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    match escape
+                    {
+                        b'"' | b'\\' | b'/' => bytes.push(escape),
+                        b'b' => bytes.push(8),
+                        b'f' => bytes.push(12),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'u' =>
+                        {
+                            let mut code = self.hex4()?;
+
+                            // surrogate pairs for characters outside the BMP
+                            if (0xD800..0xDC00).contains(&code) && self.text[self.pos..].starts_with(b"\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+
+                            let c = std::char::from_u32(code).unwrap_or('\u{FFFD}');
+                            let mut buffer = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                },
+                _ => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String>
+    {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("invalid escape"))?;
+        let code = u32::from_str_radix(&String::from_utf8_lossy(digits), 16).map_err(|_| self.error("invalid escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // the text of a value, escaping everything outside ASCII so characters
+    // beyond the BMP become surrogate pairs
+    fn write(json: &Json) -> String
+    {
+        match json
+        {
+            Json::Null => "null".to_string(),
+            Json::Bool(b) => b.to_string(),
+            Json::Number(n) => n.to_string(),
+            Json::String(s) =>
+            {
+                let mut text = String::from("\"");
+                for c in s.chars()
+                {
+                    match c
+                    {
+                        '"' => text.push_str("\\\""),
+                        '\\' => text.push_str("\\\\"),
+                        '\n' => text.push_str("\\n"),
+                        ' '..='~' => text.push(c),
+                        _ =>
+                        {
+                            let mut units = [0; 2];
+                            for unit in c.encode_utf16(&mut units)
+                            {
+                                text.push_str(&format!("\\u{:04x}", unit));
+                            }
+                        },
+                    }
+                }
+                text + "\""
+            },
+            Json::Array(items) => format!("[{}]", items.iter().map(write).collect::<Vec<_>>().join(",")),
+            Json::Object(members) => format!("{{{}}}", members.iter()
+                .map(|(name, value)| format!("{}:{}", write(&Json::String(name.clone())), write(value)))
+                .collect::<Vec<_>>().join(",")),
+        }
+    }
+
+    #[test]
+    fn escapes()
+    {
+        let json = Json::parse(r#" { "text" : "a\"b\\c\/d\n\t\u00e9\ud83d\ude00" } "#).unwrap();
+        assert_eq!(json.get("text").and_then(Json::as_str), Some("a\"b\\c/d\n\t\u{e9}\u{1F600}"));
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let json = Json::Object(vec![
+            ("name".to_string(), Json::String("caf\u{e9} \"\u{1F47E}\"\\\n".to_string())),
+            ("values".to_string(), Json::Array(vec![Json::Number(-2.5), Json::Bool(true), Json::Null])),
+            ("empty".to_string(), Json::Object(Vec::new())),
+        ]);
+
+        let text = write(&json);
+        assert!(text.contains("\\ud83d\\udc7e"));
+        assert_eq!(Json::parse(&text), Ok(json));
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("\"\\x\"").is_err());
+        assert!(Json::parse("{} []").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::collections::HashMap;
+use sdl2::keyboard::Keycode;
+
+use crate::config::{self, Config};
+
+// default layout, the hex keypad mapped onto the left side of a QWERTY keyboard:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+const DEFAULT_LAYOUT: [(Keycode, usize); 16] =
+[
+    (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+    (Keycode::Q, 0x4),    (Keycode::W, 0x5),    (Keycode::E, 0x6),    (Keycode::R, 0xD),
+    (Keycode::A, 0x7),    (Keycode::S, 0x8),    (Keycode::D, 0x9),    (Keycode::F, 0xE),
+    (Keycode::Z, 0xA),    (Keycode::X, 0x0),    (Keycode::C, 0xB),    (Keycode::V, 0xF),
+];
+
+// host keys to CHIP-8 keys, several host keys may drive the same CHIP-8 key
+pub struct KeyMap
+{
+    bindings: HashMap<Keycode, usize>,
+}
+
+impl KeyMap
+{
+    pub fn new() -> KeyMap
+    {
+        KeyMap { bindings: DEFAULT_LAYOUT.iter().cloned().collect() }
+    }
+
+    // applies the [keys] section on top of the default layout. every entry
+    // replaces all the host keys of one CHIP-8 key, e.g. for AZERTY:
+    //
+    //   [keys]
+    //   4 = A
+    //   5 = Z
+    //   7 = Q
+    //   A = W, Keypad 0
+    pub fn from_config(config: &Config) -> Result<KeyMap, String>
+    {
+        let mut keymap = KeyMap::new();
+
+        if let Some(section) = config.section("keys")
+        {
+            for (name, value) in &section.entries
+            {
+                let key = parse_key(name).ok_or(format!("[keys]: invalid CHIP-8 key {}", name))?;
+
+                keymap.bindings.retain(|_, k| *k != key);
+                for host in config::list(value)
+                {
+                    let keycode = Keycode::from_name(host).ok_or(format!("[keys]: unknown key {}", host))?;
+                    keymap.bindings.insert(keycode, key);
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn lookup(&self, keycode: Keycode) -> Option<usize>
+    {
+        self.bindings.get(&keycode).cloned()
+    }
+}
+
+// a single hex digit naming one of the 16 CHIP-8 keys
+pub fn parse_key(name: &str) -> Option<usize>
+{
+    match usize::from_str_radix(name, 16)
+    {
+        Ok(key) if key < 16 && name.len() == 1 => Some(key),
+        _ => None,
+    }
+}
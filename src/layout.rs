@@ -0,0 +1,113 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::config::Config;
+use crate::dale8::MemoryLayout;
+use crate::options::Options;
+
+// memory layouts of the machines CHIP-8 ran on
+//
+//   default   programs at 0x200, font at 0x000
+//   modern    programs at 0x200, font at 0x050 like most current interpreters
+//   vip       COSMAC VIP: the interpreter keeps its variables, stack and
+//             display in the top 352 bytes
+//   eti-660   programs at 0x600
+//   dream6800 DREAM 6800: programs at 0x200, display and stack from 0xF00
+pub const PRESETS: [(&str, MemoryLayout); 5] =
+[
+    ("default", MemoryLayout { program_start: 0x200, font_base: 0x000, size: 4096, reserved: 0 }),
+    ("modern", MemoryLayout { program_start: 0x200, font_base: 0x050, size: 4096, reserved: 0 }),
+    ("vip", MemoryLayout { program_start: 0x200, font_base: 0x000, size: 4096, reserved: 0x160 }),
+    ("eti-660", MemoryLayout { program_start: 0x600, font_base: 0x000, size: 4096, reserved: 0 }),
+    ("dream6800", MemoryLayout { program_start: 0x200, font_base: 0x000, size: 4096, reserved: 0x100 }),
+];
+
+pub fn preset(name: &str) -> Result<MemoryLayout, String>
+{
+    PRESETS.iter().find(|p| p.0 == name).map(|p| p.1)
+        .ok_or(format!("unknown memory layout {}, expected one of {}", name,
+            PRESETS.iter().map(|p| p.0).collect::<Vec<&str>>().join(", ")))
+}
+
+// the preset from --layout or [memory] layout, with the addresses of the
+// [memory] section on top:
+//
+//   [memory]
+//   layout = eti-660
+//   font_base = 0x050
+pub fn layout(config: &Config, options: &Options) -> Result<MemoryLayout, String>
+{
+    let name = match options.layout
+    {
+        Some(ref name) => name.clone(),
+        None => config.get("memory", "layout").unwrap_or("default").to_string(),
+    };
+    let mut layout = preset(&name)?;
+
+    if let Some(address) = address(config, "program_start")? { layout.program_start = address as u16; }
+    if let Some(address) = address(config, "font_base")? { layout.font_base = address as u16; }
+    if let Some(size) = address(config, "size")? { layout.size = size; }
+    if let Some(reserved) = address(config, "reserved")? { layout.reserved = reserved; }
+
+    Ok(layout)
+}
+
+// decimal or 0x-prefixed hexadecimal
+fn address(config: &Config, name: &str) -> Result<Option<usize>, String>
+{
+    let text = match config.get("memory", name)
+    {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    value.map(Some).map_err(|_| format!("[memory]: invalid {} {}", name, text))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::options;
+
+    fn options(args: &[&str]) -> Options
+    {
+        let args: Vec<String> = ["dale8"].iter().chain(args).chain(&["rom"]).map(|a| a.to_string()).collect();
+        options::parse(&args).unwrap()
+    }
+
+    #[test]
+    fn preset_with_overrides()
+    {
+        let config = Config::parse("[memory]\nlayout = vip\nfont_base = 0x050\nreserved = 256").unwrap();
+        assert_eq!(layout(&config, &options(&[])), Ok(MemoryLayout { program_start: 0x200, font_base: 0x050, size: 4096, reserved: 256 }));
+
+        // --layout picks the preset, the [memory] addresses still apply
+        assert_eq!(layout(&config, &options(&["--layout", "eti-660"])),
+                   Ok(MemoryLayout { program_start: 0x600, font_base: 0x050, size: 4096, reserved: 256 }));
+        assert_eq!(layout(&Config::default(), &options(&[])), preset("default"));
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(preset("c64").is_err());
+        assert!(layout(&Config::parse("[memory]\nsize = 4k").unwrap(), &options(&[])).is_err());
+        assert!(layout(&Config::parse("[memory]\nprogram_start = 0xZZ").unwrap(), &options(&[])).is_err());
+    }
+}
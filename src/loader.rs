@@ -0,0 +1,195 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::cartridge;
+use crate::config::Config;
+use crate::dale8::VM;
+use crate::gif;
+use crate::inflate;
+use crate::png;
+
+// extensions of the ROM files picked from archives
+pub const EXTENSIONS: [&str; 4] = ["c8", "ch8", "sc8", "xo8"];
+
+// a file inside a zip archive, from its central directory
+struct ZipEntry
+{
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+// loads a ROM from a file, from stdin when the path is "-", from a zip
+// archive or from an Octo cartridge. archives holding several ROMs need
+// `entry` to choose one, or the user is asked. cartridges come with their
+// own settings, which are returned
+pub fn load(vm: &mut VM, path: &str, entry: Option<&str>) -> Result<Option<Config>, String>
+{
+    let lowercase = path.to_lowercase();
+    let data = if path == "-"
+    {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data).map_err(|e| format!("stdin: {}", e))?;
+        data
+    }
+    else if lowercase.ends_with(".zip") || lowercase.ends_with(".gif")
+    {
+        fs::read(path).map_err(|e| format!("{}: {}", path, e))?
+    }
+    else
+    {
+        return vm.load_application(path).map(|_| None);
+    };
+
+    if gif::is_gif(&data)
+    {
+        let cartridge = cartridge::load(&data).map_err(|e| format!("{}: {}", path, e))?;
+        vm.load_rom_bytes(&cartridge.program).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(Some(cartridge.config));
+    }
+
+    if !data.starts_with(b"PK\x03\x04") && !data.starts_with(b"PK\x05\x06")
+    {
+        return vm.load_rom_bytes(&data).map(|_| None).map_err(|e| format!("{}: {}", path, e));
+    }
+
+    let entries = zip_entries(&data).map_err(|e| format!("{}: {}", path, e))?;
+    let roms: Vec<&ZipEntry> = entries.iter().filter(|e| is_rom(&e.name)).collect();
+
+    let chosen = match entry
+    {
+        Some(name) => *roms.iter().find(|e| e.name == name || e.name.rsplit('/').next() == Some(name))
+            .ok_or(format!("{}: no ROM named {}", path, name))?,
+        None => match roms.len()
+        {
+            0 => return Err(format!("{}: no ROM in the archive", path)),
+            1 => roms[0],
+            _ if path == "-" => return Err(format!("{}: several ROMs in the archive, choose one with --entry", path)),
+            _ => roms[pick(&roms.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>())?],
+        },
+    };
+
+    let rom = extract(&data, chosen).map_err(|e| format!("{}: {}: {}", path, chosen.name, e))?;
+    vm.load_rom_bytes(&rom).map(|_| None).map_err(|e| format!("{}: {}: {}", path, chosen.name, e))
+}
+
+fn is_rom(name: &str) -> bool
+{
+    let name = name.to_lowercase();
+    EXTENSIONS.iter().any(|e| name.ends_with(&format!(".{}", e)))
+}
+
+// console picker, returns the index of the chosen name
+fn pick(names: &[&str]) -> Result<usize, String>
+{
+    println!("the archive holds several ROMs:");
+    for (i, name) in names.iter().enumerate()
+    {
+        println!("  {}) {}", i + 1, name);
+    }
+
+    loop
+    {
+        print!("choose one [1-{}]: ", names.len());
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())? == 0
+        {
+            return Err("no ROM chosen".to_string());
+        }
+        match line.trim().parse::<usize>()
+        {
+            Ok(n) if (1..=names.len()).contains(&n) => return Ok(n - 1),
+            _ => println!("invalid choice"),
+        }
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, String>
+{
+    data.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| "truncated archive".to_string())
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, String>
+{
+    data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| "truncated archive".to_string())
+}
+
+fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>, String>
+{
+    // the end of central directory record is last, followed by a comment of
+    // up to 64 KiB
+    let end = (0..data.len().saturating_sub(21)).rev().take(65536 + 22)
+        .find(|&pos| data[pos..].starts_with(b"PK\x05\x06"))
+        .ok_or("not a zip archive")?;
+
+    let count = u16_at(data, end + 10)? as usize;
+    let mut pos = u32_at(data, end + 16)? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count
+    {
+        if u32_at(data, pos)? != 0x02014B50
+        {
+            return Err("invalid central directory".to_string());
+        }
+
+        let name_length = u16_at(data, pos + 28)? as usize;
+        let extra_length = u16_at(data, pos + 30)? as usize;
+        let comment_length = u16_at(data, pos + 32)? as usize;
+        let name = data.get(pos + 46..pos + 46 + name_length).ok_or("truncated archive")?;
+
+        entries.push(ZipEntry
+        {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(data, pos + 10)?,
+            crc: u32_at(data, pos + 16)?,
+            compressed_size: u32_at(data, pos + 20)? as usize,
+            size: u32_at(data, pos + 24)? as usize,
+            offset: u32_at(data, pos + 42)? as usize,
+        });
+        pos += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+fn extract(data: &[u8], entry: &ZipEntry) -> Result<Vec<u8>, String>
+{
+    if u32_at(data, entry.offset)? != 0x04034B50
+    {
+        return Err("invalid local header".to_string());
+    }
+
+    let start = entry.offset + 30 + u16_at(data, entry.offset + 26)? as usize + u16_at(data, entry.offset + 28)? as usize;
+    let compressed = data.get(start..start + entry.compressed_size).ok_or("truncated archive")?;
+
+    let rom = match entry.method
+    {
+        0 => compressed.to_vec(),
+        8 => inflate::inflate(compressed)?,
+        method => return Err(format!("unsupported compression method {}", method)),
+    };
+
+    if rom.len() != entry.size || png::crc32(&rom) != entry.crc
+    {
+        return Err("corrupted data".to_string());
+    }
+    Ok(rom)
+}
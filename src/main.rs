@@ -28,23 +28,63 @@
 extern crate sdl2;
 
 use std::path::Path;
-use sdl2::rect::Rect;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::Color;
+use sdl2::video::FullscreenType;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioSpecWAV, AudioCVT};
 
 mod dale8;
+mod capture;
+mod cdp1802;
+mod cartridge;
+mod config;
+mod controller;
+mod coverage;
+mod debugger;
+mod disasm;
+mod heatmap;
+mod json;
+mod inflate;
+mod keymap;
+mod layout;
+mod loader;
+mod octo;
+mod options;
+mod osd;
+mod filters;
+mod font;
+mod gif;
+mod palette;
+mod phosphor;
+mod png;
+mod profiler;
+mod renderer;
+mod romdb;
+mod scanner;
+mod scheduler;
+mod sha1;
+mod timing;
+mod view;
+mod vip;
 use std::env;
-
-const SCREEN_WIDTH: u32 = 64;
-const SCREEN_HEIGHT: u32 = 32;
-
+use capture::AudioCapture;
+use config::Config;
+use controller::Controllers;
+use coverage::Symbols;
+use debugger::DebugView;
+use heatmap::Heatmap;
+use keymap::KeyMap;
+use osd::Osd;
+use renderer::Renderer;
+use scheduler::Scheduler;
+use view::Scaling;
+use vip::Vip;
+
+// default window size, as a multiple of the framebuffer
 const DISPLAY_MODIFIER: u32 = 10;
 
-const DISPLAY_WIDTH: u32 = SCREEN_WIDTH * DISPLAY_MODIFIER;
-const DISPLAY_HEIGHT: u32 = SCREEN_HEIGHT * DISPLAY_MODIFIER;
-
 struct Sound {
     data: Vec<u8>,
     volume: f32,
@@ -65,35 +105,301 @@ impl AudioCallback for Sound {
 fn main() 
 {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2
+    let options = match options::parse(&args)
+    {
+        Ok(options) => options,
+        Err(e) =>
+        {
+            println!("{}\n\n{}", e, options::USAGE);
+            return;
+        }
+    };
+    let mut vm = dale8::VM::new();
+    let settings = match loader::load(&mut vm, &options.rom, options.entry.as_deref())
+    {
+        Ok(settings) => settings,
+        Err(e) =>
+        {
+            println!("failed to load the rom: {}", e);
+            return;
+        }
+    };
+
+    let (start, end) = vm.program_range();
+    let rom = &vm.memory()[start as usize..end as usize];
+    let config = match Config::load_for_rom(options.config.as_deref(), &options.rom, rom, settings)
+    {
+        Ok(config) => config,
+        Err(e) =>
+        {
+            println!("failed to load configuration: {}", e);
+            return;
+        }
+    };
+
+    // known to the ROM database
+    let title = match (config.get("rom", "title"), config.get("rom", "authors"))
+    {
+        (Some(title), Some(authors)) => format!("{} by {}", title, authors),
+        (Some(title), None) => title.to_string(),
+        _ => String::new(),
+    };
+    if !title.is_empty()
     {
-        println!("syntax: dale8 [rom_file]");
+        println!("{}", title);
+    }
+
+    if let Err(e) = layout::layout(&config, &options).and_then(|layout| vm.set_layout(layout))
+    {
+        println!("failed to set up the memory layout: {}", e);
         return;
     }
-    let mut vm = dale8::VM::new();
-    if !vm.load_application(&args[1])
+
+    let (start, end) = vm.program_range();
+    let scan = scanner::scan(vm.memory(), start, end);
+    if options.scan
     {
-        println!("failed load rom");
-        return
+        print!("{}", scan.report());
+        return;
     }
 
+    let platform = match scanner::quirks(&config, &options, &scan)
+    {
+        Ok((platform, quirks)) =>
+        {
+            vm.quirks = quirks;
+            platform
+        },
+        Err(e) =>
+        {
+            println!("failed to set up the quirks: {}", e);
+            return;
+        }
+    };
+
+    // hybrid VIP programs call machine code with 0NNN
+    match config.value::<bool>("emulation", "hybrid")
+    {
+        Ok(hybrid) => if hybrid.unwrap_or(!scan.machine_calls.is_empty())
+        {
+            vm.enable_machine_code();
+        },
+        Err(e) =>
+        {
+            println!("failed to load configuration: {}", e);
+            return;
+        }
+    }
+
+    match timing::timing(&config, &options)
+    {
+        Ok(timing) => vm.timing = timing,
+        Err(e) =>
+        {
+            println!("failed to set up the timing: {}", e);
+            return;
+        }
+    }
+
+    if let Err(e) = font::fonts(&config, &options, platform).and_then(|(small, big)| vm.set_font(&small, &big))
+    {
+        println!("failed to load the font: {}", e);
+        return;
+    }
+
+    let mut capture = None;
+    if let Some(ref filename) = options.wav
+    {
+        match AudioCapture::create(filename)
+        {
+            Ok(c) => capture = Some(c),
+            Err(e) =>
+            {
+                println!("failed to create {}: {}", filename, e);
+                return;
+            }
+        }
+    }
+
+    let mut symbols = None;
+    if let Some(ref filename) = options.symbols
+    {
+        match Symbols::load(filename)
+        {
+            Ok(s) => symbols = Some(s),
+            Err(e) =>
+            {
+                println!("failed to load symbols: {}", e);
+                return;
+            }
+        }
+    }
+    if options.coverage.is_some()
+    {
+        vm.enable_coverage();
+    }
+    if options.profile.is_some()
+    {
+        vm.enable_profiler();
+    }
+
+    let mut vip = None;
+    if options.vip
+    {
+        let (start, end) = vm.program_range();
+        match Vip::load(&config, &vm.memory()[start as usize..end as usize])
+        {
+            Ok(v) => vip = Some(v),
+            Err(e) =>
+            {
+                println!("failed to set up the VIP: {}", e);
+                return;
+            }
+        }
+    }
+
+    let mut renderer = match Renderer::new(&config, &options, vm.gfx.len())
+    {
+        Ok(renderer) => renderer,
+        Err(e) =>
+        {
+            println!("failed to set up the display: {}", e);
+            return;
+        }
+    };
+
+    let mut scheduler = match Scheduler::new(&config, &options)
+    {
+        Ok(scheduler) => scheduler,
+        Err(e) =>
+        {
+            println!("failed to set up the scheduler: {}", e);
+            return;
+        }
+    };
+
+    if let Some(frames) = options.headless
+    {
+        let mut heatmap = options.heatmap.as_ref().map(|_| Heatmap::new());
+        for _ in 0..frames
+        {
+            scheduler.run_frame(&mut vm);
+            if let Some(ref mut vip) = vip
+            {
+                vip.run_frame(&vm);
+            }
+            record_sound(&mut capture, vip.as_ref().map_or(vm.sound_active(), |v| v.sound_active()));
+            renderer.update(displayed(&vm, &vip));
+            if let Some(ref mut heatmap) = heatmap
+            {
+                heatmap.update(&mut vm);
+            }
+        }
+        finish_capture(capture);
+        write_reports(&options, &vm, symbols.as_ref(), vip.as_ref());
+
+        if let (Some(heatmap), Some(filename)) = (heatmap, options.heatmap.as_ref())
+        {
+            if let Err(e) = heatmap.export(filename)
+            {
+                println!("failed to save heatmap: {}", e);
+            }
+        }
+
+        if let Some(ref filename) = options.screenshot
+        {
+            if let Err(e) = renderer.screenshot(displayed(&vm, &vip), vm.screen_size(), filename)
+            {
+                println!("failed to save screenshot: {}", e);
+            }
+        }
+        return;
+    }
+
+    let keymap = match KeyMap::from_config(&config)
+    {
+        Ok(keymap) => keymap,
+        Err(e) =>
+        {
+            println!("failed to load key bindings: {}", e);
+            return;
+        }
+    };
+
+    let scale = match options.scale
+    {
+        Some(scale) => scale,
+        None => match config.value("display", "scale")
+        {
+            Ok(scale) => scale.unwrap_or(DISPLAY_MODIFIER),
+            Err(e) =>
+            {
+                println!("{}", e);
+                return;
+            }
+        },
+    }.max(1);
+
+    let scaling = options.scaling.as_deref().or_else(|| config.get("display", "scaling")).unwrap_or("integer");
+    let scaling = match Scaling::parse(scaling)
+    {
+        Ok(scaling) => scaling,
+        Err(e) =>
+        {
+            println!("{}", e);
+            return;
+        }
+    };
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let audio_subsystem = sdl_context.audio().unwrap();
-    let window = video_subsystem.window("dale8", DISPLAY_WIDTH, DISPLAY_HEIGHT).position_centered().build()
-        .map_err(|e| e.to_string()).unwrap();
-
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string()).unwrap();
+    let mut controllers = match Controllers::new(sdl_context.game_controller().unwrap(), &config)
+    {
+        Ok(controllers) => controllers,
+        Err(e) =>
+        {
+            println!("failed to load controller mapping: {}", e);
+            return;
+        }
+    };
+    let (width, height) = renderer.output_size(vm.screen_size());
+    let window_title = if title.is_empty() { "dale8".to_string() } else { format!("dale8 - {}", title) };
+    let window = video_subsystem.window(&window_title, width as u32 * scale, height as u32 * scale).position_centered()
+        .resizable().build().map_err(|e| e.to_string()).unwrap();
+
+    let mut canvas_builder = window.into_canvas();
+    if scheduler.vsync
+    {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().map_err(|e| e.to_string()).unwrap();
     let texture_creator = canvas.texture_creator();
 
-    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH, 
-        SCREEN_HEIGHT).map_err(|e| e.to_string()).unwrap();
+    // recreated whenever the framebuffer resolution changes
+    let mut texture_size = (width, height);
+    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, 
+        height as u32).map_err(|e| e.to_string()).unwrap();
+
+    let mut screenshots = 0;
+
+    let mut osd = Osd::new();
+    osd.visible = match config.value("display", "osd")
+    {
+        Ok(visible) => visible.unwrap_or(false),
+        Err(e) =>
+        {
+            println!("failed to load configuration: {}", e);
+            return;
+        }
+    };
+    let mut osd_shown = false;
+
+    let mut debug_view: Option<DebugView> = None;
 
     let mut _audio_device = None;
     let has_sound = Path::new("beep.wav").exists();
 
-    let mut timer  = 0;
-
     'mainloop: loop 
     {
         for event in sdl_context.event_pump().unwrap().poll_iter() 
@@ -103,88 +409,201 @@ fn main()
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } |
                 Event::Quit { .. } => break 'mainloop,
 
-                // key down
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => { vm.key[1] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } => { vm.key[2] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { vm.key[3] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => { vm.key[0xC] = 1; },
-
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } => { vm.key[4] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::W), .. } => { vm.key[5] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::E), .. } => { vm.key[6] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::R), .. } => { vm.key[0xD] = 1; },
-
-                Event::KeyDown { keycode: Some(Keycode::A), .. } => { vm.key[7] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::S), .. } => { vm.key[8] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::D), .. } => { vm.key[9] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::F), .. } => { vm.key[0xE] = 1; },
-
-                Event::KeyDown { keycode: Some(Keycode::Z), .. } => { vm.key[0xA] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::X), .. } => { vm.key[0] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::C), .. } => { vm.key[0xB] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::V), .. } => { vm.key[0xF] = 1; },
-
-                // key up
-                Event::KeyUp { keycode: Some(Keycode::Num1), .. } => { vm.key[1] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::Num2), .. } => { vm.key[2] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::Num3), .. } => { vm.key[3] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::Num4), .. } => { vm.key[0xC] = 0; },
-
-                Event::KeyUp { keycode: Some(Keycode::Q), .. } => { vm.key[4] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::W), .. } => { vm.key[5] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::E), .. } => { vm.key[6] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::R), .. } => { vm.key[0xD] = 0; },
-
-                Event::KeyUp { keycode: Some(Keycode::A), .. } => { vm.key[7] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::S), .. } => { vm.key[8] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::D), .. } => { vm.key[9] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::F), .. } => { vm.key[0xE] = 0; },
-
-                Event::KeyUp { keycode: Some(Keycode::Z), .. } => { vm.key[0xA] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::X), .. } => { vm.key[0] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::C), .. } => { vm.key[0xB] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::V), .. } => { vm.key[0xF] = 0; },
-
-                _ => {}
+                // with the debugger open, closing a window doesn't quit by itself
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } =>
+                {
+                    if debug_view.as_ref().map(|d| d.window_id()) == Some(window_id)
+                    {
+                        debug_view = None;
+                    }
+                    else
+                    {
+                        break 'mainloop;
+                    }
+                },
+
+                // alt+enter
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) =>
+                {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state()
+                    {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    if let Err(e) = window.set_fullscreen(fullscreen)
+                    {
+                        osd.error(&format!("failed to toggle fullscreen: {}", e));
+                    }
+                    vm.draw_flag = true;
+                },
+
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } =>
+                {
+                    vm.draw_flag = true;
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F1), .. } =>
+                {
+                    osd.visible = !osd.visible;
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F2), .. } =>
+                {
+                    osd.message(&format!("palette: {}", renderer.palettes.next().name));
+                    vm.draw_flag = true;
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F3), .. } =>
+                {
+                    renderer.phosphor.mode = renderer.phosphor.mode.next();
+                    osd.message(&format!("persistence: {}", renderer.phosphor.mode.name()));
+                    vm.draw_flag = true;
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } |
+                Event::KeyDown { keycode: Some(Keycode::Pause), .. } =>
+                {
+                    scheduler.toggle_pause();
+                    osd.message(if scheduler.paused { "paused" } else { "resumed" });
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F6), .. } =>
+                {
+                    scheduler.advance_frame();
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F7), .. } =>
+                {
+                    osd.message(&format!("speed: {}x", scheduler.cycle_slow_motion()));
+                },
+
+                // fast-forward while held
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } =>
+                {
+                    scheduler.fast_forward = true;
+                },
+
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } =>
+                {
+                    scheduler.fast_forward = false;
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } =>
+                {
+                    if debug_view.is_some()
+                    {
+                        debug_view = None;
+                    }
+                    else
+                    {
+                        match DebugView::new(&video_subsystem)
+                        {
+                            Ok(view) => debug_view = Some(view),
+                            Err(e) => osd.error(&format!("failed to open the debugger: {}", e)),
+                        }
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::F12), .. } =>
+                {
+                    // pick the first unused name
+                    let mut filename;
+                    loop
+                    {
+                        screenshots += 1;
+                        filename = format!("screenshot{}.png", screenshots);
+                        if !Path::new(&filename).exists()
+                        {
+                            break;
+                        }
+                    }
+
+                    match renderer.screenshot(displayed(&vm, &vip), vm.screen_size(), &filename)
+                    {
+                        Ok(()) => osd.message(&format!("saved {}", filename)),
+                        Err(e) => osd.error(&format!("failed to save screenshot: {}", e)),
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(keycode), .. } =>
+                {
+                    if let Some(key) = keymap.lookup(keycode)
+                    {
+                        vm.press_key(key);
+                    }
+                },
+
+                Event::KeyUp { keycode: Some(keycode), .. } =>
+                {
+                    if let Some(key) = keymap.lookup(keycode)
+                    {
+                        vm.release_key(key);
+                    }
+                },
+
+                _ => controllers.handle_event(&event, &mut vm),
             }
         }
 
-        if timer == 2000
+        scheduler.run(&mut vm, |vm|
         {
-            vm.emulate_cycle();
-            timer = 0;
-        }
-        else 
-        {
-            timer += 1;
-        }
+            if let Some(ref mut vip) = vip
+            {
+                vip.run_frame(vm);
+            }
+            record_sound(&mut capture, vip.as_ref().map_or(vm.sound_active(), |v| v.sound_active()));
+            if renderer.update(displayed(vm, &vip))
+            {
+                vm.draw_flag = true;
+            }
+            if let Some(ref mut view) = debug_view
+            {
+                view.update(vm);
+            }
+        });
+
+        osd.update(&scheduler);
+
+        // with vsync, presenting every frame is what paces the loop. the
+        // OSD needs one more present to disappear once it is no longer active
+        let present = vm.draw_flag || scheduler.vsync || osd.active() || osd_shown;
+        osd_shown = osd.active();
 
         if vm.draw_flag
         {
-            texture.with_lock(None, |buffer: &mut [u8], pitch: usize| 
+            let image = renderer.render(displayed(&vm, &vip), vm.screen_size());
+
+            // filters render at a higher resolution than the framebuffer
+            if texture_size != (image.width, image.height)
             {
-                for y in 0..SCREEN_HEIGHT as usize
-                {
-                    for x in 0..SCREEN_WIDTH as usize
-                    {
-                        let offset: usize = y*pitch + x*3;
-                        let mut color: u8 = 0;
-                        if vm.gfx[((y * SCREEN_WIDTH as usize) + x) as usize] != 0
-                        {
-                            color = 255;
-                        }
-                        buffer[offset] = color;
-                        buffer[offset + 1] = color;
-                        buffer[offset + 2] = color;
-                    }
-                }
-            }).unwrap();
+                texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, image.width as u32, 
+                    image.height as u32).map_err(|e| e.to_string()).unwrap();
+                texture_size = (image.width, image.height);
+            }
+            texture.update(None, &image.data, image.pitch()).unwrap();
+
+            vm.draw_flag = false;
+        }
 
+        if present
+        {
+            let (width, height) = renderer.output_size(vm.screen_size());
+            let output = canvas.output_size().unwrap();
+            let viewport = view::viewport(output, (width as u32, height as u32), scaling);
+
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
             canvas.clear();
-            canvas.copy(&texture, None, Some(Rect::new(0, 0, DISPLAY_WIDTH, DISPLAY_HEIGHT))).unwrap();
+            canvas.copy(&texture, None, Some(viewport)).unwrap();
+            osd.draw(&mut canvas, &scheduler);
             canvas.present();
+        }
 
-            vm.draw_flag = false;
+        if let Some(ref mut view) = debug_view
+        {
+            view.draw(&vm);
         }
 
         if vm.beep_flag
@@ -222,10 +641,79 @@ fn main()
             }
             else 
             {
-                println!("BEEP");
+                osd.message("BEEP");
             }
 
             vm.beep_flag = false;
         }
+
+        scheduler.end_frame();
+    }
+
+    finish_capture(capture);
+    write_reports(&options, &vm, symbols.as_ref(), vip.as_ref());
+
+    if scheduler.dropped_frames > 0
+    {
+        println!("dropped {} of {} frames", scheduler.dropped_frames, scheduler.frames);
+    }
+}
+
+// called once per frame, after the timers have ticked
+fn record_sound(capture: &mut Option<AudioCapture>, active: bool)
+{
+    if let Some(c) = capture
+    {
+        if let Err(e) = c.render(active, 1.0 / dale8::TIMER_FREQUENCY as f64)
+        {
+            println!("audio capture failed: {}", e);
+            *capture = None;
+        }
+    }
+}
+
+fn finish_capture(capture: Option<AudioCapture>)
+{
+    if let Some(c) = capture
+    {
+        if let Err(e) = c.finish()
+        {
+            println!("failed to write audio capture: {}", e);
+        }
+    }
+}
+
+// the framebuffer shown, the one of the VIP when it runs
+fn displayed<'a>(vm: &'a dale8::VM, vip: &'a Option<Vip>) -> &'a [u8]
+{
+    match vip
+    {
+        Some(vip) => vip.gfx(),
+        None => &vm.gfx,
+    }
+}
+
+fn write_reports(options: &options::Options, vm: &dale8::VM, symbols: Option<&Symbols>, vip: Option<&Vip>)
+{
+    if let Some(vip) = vip
+    {
+        println!("{}", vip.comparison.report());
+    }
+
+    if let (Some(filename), Some(coverage)) = (options.coverage.as_ref(), vm.coverage())
+    {
+        let (start, end) = vm.program_range();
+        if let Err(e) = coverage::write_report(filename, coverage, vm.memory(), start, end, symbols)
+        {
+            println!("failed to write coverage report: {}", e);
+        }
     }
-}
\ No newline at end of file
+
+    if let (Some(filename), Some(profiler)) = (options.profile.as_ref(), vm.profiler())
+    {
+        if let Err(e) = profiler.write_report(filename, vm.memory(), symbols)
+        {
+            println!("failed to write profile: {}", e);
+        }
+    }
+}
@@ -0,0 +1,925 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::collections::HashMap;
+
+// assembler for Octo, the language programs are shared in as cartridges:
+// labels, aliases, constants, macros, :calc expressions, the structured
+// if/loop statements and the CHIP-8, SUPER-CHIP and XO-CHIP instructions.
+// :stringmode is left out
+//
+//   : main
+//     i := sprite
+//     loop
+//       sprite v0 v1 5
+//       v0 += 1
+//       if v0 == 64 then v0 := 0
+//     again
+//   : sprite 0xF0 0x90 0x90 0x90 0xF0
+
+const PROGRAM_START: usize = 0x200;
+
+// registers used by the comparisons that CHIP-8 has no instruction for
+const COMPARE_TEMP: u8 = 0xF;
+
+// how a forward reference is filled in once the label is defined
+#[derive(Clone, Copy)]
+enum Fixup
+{
+    Address,    // the NNN of the instruction
+    Long,       // 16 bits, for i := long and :pointer
+    Unpack(u8), // v0 := nibble and high bits, v1 := low bits
+}
+
+struct Macro
+{
+    arguments: Vec<String>,
+    body: Vec<(String, usize)>,
+}
+
+struct Assembler
+{
+    tokens: Vec<(String, usize)>, // with their line
+    pos: usize,
+    line: usize,
+
+    rom: Vec<u8>,
+    here: usize,
+    end: usize, // after the last byte written
+
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, String, Fixup, usize)>, // address, label, kind, line
+
+    blocks: Vec<usize>,                // jumps of if ... begin and else to patch
+    loops: Vec<(usize, Vec<usize>)>,   // start of each loop and jumps of its whiles
+}
+
+// the program assembled from `source`, starting at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, String>
+{
+    let mut assembler = Assembler
+    {
+        tokens: tokenize(source),
+        pos: 0,
+        line: 1,
+
+        rom: vec![0; 0x10000],
+        here: PROGRAM_START + 2, // room for the jump to main
+        end: PROGRAM_START + 2,
+
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+
+        blocks: Vec::new(),
+        loops: Vec::new(),
+    };
+
+    while assembler.pos < assembler.tokens.len()
+    {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+// whitespace separated tokens, strings in double quotes being one. comments
+// run from # to the end of the line
+fn tokenize(source: &str) -> Vec<(String, usize)>
+{
+    let mut tokens = Vec::new();
+    for (number, line) in source.lines().enumerate()
+    {
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek()
+        {
+            if c.is_whitespace()
+            {
+                chars.next();
+            }
+            else if c == '#'
+            {
+                break;
+            }
+            else if c == '"'
+            {
+                chars.next();
+                let text: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                tokens.push((format!("\"{}\"", text), number + 1));
+            }
+            else
+            {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek()
+                {
+                    if c.is_whitespace() || c == '#'
+                    {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push((text, number + 1));
+            }
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<f64>
+{
+    let (negative, digits) = match text.strip_prefix('-')
+    {
+        Some(digits) if !digits.is_empty() => (true, digits),
+        _ => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x")
+    {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    }
+    else if let Some(binary) = digits.strip_prefix("0b")
+    {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    }
+    else if digits.starts_with(|c: char| c.is_ascii_digit())
+    {
+        digits.parse().ok()?
+    }
+    else
+    {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl Assembler
+{
+    fn error(&self, message: &str) -> String
+    {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn next(&mut self) -> Result<String, String>
+    {
+        match self.tokens.get(self.pos)
+        {
+            Some((text, line)) =>
+            {
+                self.line = *line;
+                self.pos += 1;
+                Ok(text.clone())
+            },
+            None => Err(self.error("unexpected end of the program")),
+        }
+    }
+
+    fn peek(&self) -> Option<&str>
+    {
+        self.tokens.get(self.pos).map(|t| t.0.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String>
+    {
+        let token = self.next()?;
+        if token != text
+        {
+            return Err(self.error(&format!("expected '{}', found '{}'", text, token)));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8)
+    {
+        let address = self.here % self.rom.len();
+        self.rom[address] = byte;
+        self.here = address + 1;
+        self.end = self.end.max(self.here);
+    }
+
+    fn instruction(&mut self, opcode: u16)
+    {
+        self.emit((opcode >> 8) as u8);
+        self.emit(opcode as u8);
+    }
+
+    fn is_register(&self, text: &str) -> bool
+    {
+        self.aliases.contains_key(text) || register_number(text).is_some()
+    }
+
+    fn register(&mut self) -> Result<u16, String>
+    {
+        let token = self.next()?;
+        match self.aliases.get(&token).cloned().or_else(|| register_number(&token))
+        {
+            Some(register) => Ok(register as u16),
+            None => Err(self.error(&format!("expected a register, found '{}'", token))),
+        }
+    }
+
+    // a number, constant, defined label or { expression }
+    fn value(&mut self) -> Result<f64, String>
+    {
+        let token = self.next()?;
+        if token == "{"
+        {
+            return self.calc_block();
+        }
+        self.lookup(&token).ok_or_else(|| self.error(&format!("undefined value '{}'", token)))
+    }
+
+    fn lookup(&self, name: &str) -> Option<f64>
+    {
+        number(name).or_else(|| self.constants.get(name).cloned()).or_else(|| self.labels.get(name).map(|&a| a as f64))
+    }
+
+    fn byte(&mut self) -> Result<u16, String>
+    {
+        let value = self.value()?;
+        Ok(self.to_byte(value)? as u16)
+    }
+
+    // negative values are two's complement
+    fn to_byte(&self, value: f64) -> Result<u8, String>
+    {
+        if !(-128.0..=255.0).contains(&value)
+        {
+            return Err(self.error(&format!("{} doesn't fit in a byte", value)));
+        }
+        Ok((value as i64 & 0xFF) as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String>
+    {
+        let value = self.value()?;
+        if !(0.0..=15.0).contains(&value)
+        {
+            return Err(self.error(&format!("{} doesn't fit in a nibble", value)));
+        }
+        Ok(value as u16)
+    }
+
+    // an address for the instruction about to be emitted, labels defined
+    // further down are filled in at the end
+    fn address(&mut self, fixup: Fixup, limit: usize) -> Result<u16, String>
+    {
+        let token = self.next()?;
+        let value = if token == "{" { Some(self.calc_block()?) } else { self.lookup(&token) };
+        match value
+        {
+            Some(value) if value >= 0.0 && (value as usize) <= limit => Ok(value as u16),
+            Some(value) => Err(self.error(&format!("address {} out of range", value))),
+            None if is_name(&token) =>
+            {
+                self.fixups.push((self.here, token, fixup, self.line));
+                Ok(0)
+            },
+            None => Err(self.error(&format!("invalid address '{}'", token))),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String>
+    {
+        let token = self.next()?;
+        match token.as_str()
+        {
+            ":" =>
+            {
+                let name = self.next()?;
+                self.define(&name, self.here)?;
+            },
+            ":next" =>
+            {
+                // the second byte of the next instruction, for self-modifying code
+                let name = self.next()?;
+                self.define(&name, self.here + 1)?;
+            },
+            ":alias" =>
+            {
+                let name = self.next()?;
+                let register = self.register()? as u8;
+                self.aliases.insert(name, register);
+            },
+            ":const" =>
+            {
+                let name = self.next()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            },
+            ":calc" =>
+            {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            },
+            ":byte" =>
+            {
+                let byte = self.byte()?;
+                self.emit(byte as u8);
+            },
+            ":pointer" =>
+            {
+                let address = self.address(Fixup::Long, 0xFFFF)?;
+                self.instruction(address);
+            },
+            ":unpack" =>
+            {
+                let nibble = self.nibble()? as u8;
+                let address = self.address(Fixup::Unpack(nibble), 0xFFF)?;
+                self.instruction(0x6000 | (nibble as u16) << 4 | address >> 8);
+                self.instruction(0x6100 | (address & 0xFF));
+            },
+            ":org" =>
+            {
+                let address = self.value()?;
+                if address < 0.0 || address >= self.rom.len() as f64
+                {
+                    return Err(self.error(&format!("address {} out of range", address)));
+                }
+                self.here = address as usize;
+            },
+            ":call" =>
+            {
+                let address = self.address(Fixup::Address, 0xFFF)?;
+                self.instruction(0x2000 | address);
+            },
+            ":macro" => self.define_macro()?,
+            ":assert" =>
+            {
+                let message = if self.peek().is_some_and(|t| t.starts_with('"')) { self.next()? } else { "assertion failed".to_string() };
+                self.expect("{")?;
+                if self.calc_block()? == 0.0
+                {
+                    return Err(self.error(message.trim_matches('"')));
+                }
+            },
+            ":breakpoint" => { self.next()?; },
+            ":monitor" => { self.next()?; self.next()?; },
+
+            "clear" => self.instruction(0x00E0),
+            "return" | ";" => self.instruction(0x00EE),
+            "hires" => self.instruction(0x00FF),
+            "lores" => self.instruction(0x00FE),
+            "scroll-down" => { let n = self.nibble()?; self.instruction(0x00C0 | n) },
+            "scroll-up" => { let n = self.nibble()?; self.instruction(0x00D0 | n) },
+            "scroll-right" => self.instruction(0x00FB),
+            "scroll-left" => self.instruction(0x00FC),
+            "exit" => self.instruction(0x00FD),
+            "audio" => self.instruction(0xF002),
+            "plane" => { let n = self.nibble()?; self.instruction(0xF001 | n << 8) },
+            "native" => { let address = self.address(Fixup::Address, 0xFFF)?; self.instruction(address) },
+            "jump" => { let address = self.address(Fixup::Address, 0xFFF)?; self.instruction(0x1000 | address) },
+            "jump0" => { let address = self.address(Fixup::Address, 0xFFF)?; self.instruction(0xB000 | address) },
+            "bcd" => { let x = self.register()?; self.instruction(0xF033 | x << 8) },
+            "saveflags" => { let x = self.register()?; self.instruction(0xF075 | x << 8) },
+            "loadflags" => { let x = self.register()?; self.instruction(0xF085 | x << 8) },
+            "save" | "load" =>
+            {
+                let x = self.register()?;
+                if self.peek() == Some("-")
+                {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.instruction(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                }
+                else
+                {
+                    self.instruction(if token == "save" { 0xF055 } else { 0xF065 } | x << 8);
+                }
+            },
+            "sprite" =>
+            {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(0xD000 | x << 8 | y << 4 | n);
+            },
+            "delay" | "buzzer" | "pitch" =>
+            {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match token.as_str() { "delay" => 0x15, "buzzer" => 0x18, _ => 0x3A };
+                self.instruction(0xF000 | x << 8 | low);
+            },
+            "i" => self.index()?,
+            "if" => self.conditional()?,
+            "else" =>
+            {
+                let jump = self.blocks.pop().ok_or_else(|| self.error("else without if ... begin"))?;
+                self.blocks.push(self.here);
+                self.instruction(0x1000);
+                self.patch(jump, self.here);
+            },
+            "end" =>
+            {
+                let jump = self.blocks.pop().ok_or_else(|| self.error("end without if ... begin"))?;
+                self.patch(jump, self.here);
+            },
+            "loop" => self.loops.push((self.here, Vec::new())),
+            "while" =>
+            {
+                if self.loops.is_empty()
+                {
+                    return Err(self.error("while outside a loop"));
+                }
+                self.condition(true)?;
+                let here = self.here;
+                if let Some(current) = self.loops.last_mut()
+                {
+                    current.1.push(here);
+                }
+                self.instruction(0x1000);
+            },
+            "again" =>
+            {
+                let (start, whiles) = self.loops.pop().ok_or_else(|| self.error("again without loop"))?;
+                self.instruction(0x1000 | start as u16 & 0xFFF);
+                for jump in whiles
+                {
+                    self.patch(jump, self.here);
+                }
+            },
+            ":stringmode" => return Err(self.error(":stringmode isn't supported")),
+
+            _ if self.is_register(&token) =>
+            {
+                self.pos -= 1;
+                self.register_operation()?;
+            },
+            _ if self.macros.contains_key(&token) => self.expand(&token)?,
+            _ => match self.lookup(&token)
+            {
+                // plain numbers and constants are data
+                Some(value) if !self.labels.contains_key(&token) =>
+                {
+                    let byte = self.to_byte(value)?;
+                    self.emit(byte);
+                },
+
+                // anything else calls a subroutine
+                _ if is_name(&token) =>
+                {
+                    self.pos -= 1;
+                    let address = self.address(Fixup::Address, 0xFFF)?;
+                    self.instruction(0x2000 | address);
+                },
+                _ => return Err(self.error(&format!("unexpected '{}'", token))),
+            },
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, address: usize) -> Result<(), String>
+    {
+        if self.labels.insert(name.to_string(), address).is_some()
+        {
+            return Err(self.error(&format!("label '{}' defined twice", name)));
+        }
+        Ok(())
+    }
+
+    fn patch(&mut self, jump: usize, target: usize)
+    {
+        self.rom[jump] = 0x10 | (target >> 8) as u8 & 0x0F;
+        self.rom[jump + 1] = target as u8;
+    }
+
+    fn index(&mut self) -> Result<(), String>
+    {
+        let operator = self.next()?;
+        match operator.as_str()
+        {
+            "+=" => { let x = self.register()?; self.instruction(0xF01E | x << 8) },
+            ":=" => match self.peek()
+            {
+                Some("hex") => { self.next()?; let x = self.register()?; self.instruction(0xF029 | x << 8) },
+                Some("bighex") => { self.next()?; let x = self.register()?; self.instruction(0xF030 | x << 8) },
+                Some("long") =>
+                {
+                    self.next()?;
+                    self.instruction(0xF000);
+                    let address = self.address(Fixup::Long, 0xFFFF)?;
+                    self.instruction(address);
+                },
+                _ => { let address = self.address(Fixup::Address, 0xFFF)?; self.instruction(0xA000 | address) },
+            },
+            _ => return Err(self.error(&format!("unexpected 'i {}'", operator))),
+        }
+        Ok(())
+    }
+
+    fn register_operation(&mut self) -> Result<(), String>
+    {
+        let x = self.register()?;
+        let operator = self.next()?;
+
+        // operations taking a register or a byte
+        let with_register = |this: &mut Assembler, low: u16| -> Result<bool, String>
+        {
+            if this.peek().is_some_and(|t| this.is_register(t))
+            {
+                let y = this.register()?;
+                this.instruction(0x8000 | x << 8 | y << 4 | low);
+                return Ok(true);
+            }
+            Ok(false)
+        };
+
+        match operator.as_str()
+        {
+            ":=" => match self.peek()
+            {
+                Some("random") => { self.next()?; let n = self.byte()?; self.instruction(0xC000 | x << 8 | n) },
+                Some("delay") => { self.next()?; self.instruction(0xF007 | x << 8) },
+                Some("key") => { self.next()?; self.instruction(0xF00A | x << 8) },
+                _ => if !with_register(self, 0x0)?
+                {
+                    let n = self.byte()?;
+                    self.instruction(0x6000 | x << 8 | n);
+                },
+            },
+            "+=" => if !with_register(self, 0x4)?
+            {
+                let n = self.byte()?;
+                self.instruction(0x7000 | x << 8 | n);
+            },
+            "-=" => if !with_register(self, 0x5)?
+            {
+                let n = self.byte()?;
+                self.instruction(0x7000 | x << 8 | (n as u8).wrapping_neg() as u16);
+            },
+            "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=" =>
+            {
+                let low = match operator.as_str() { "=-" => 0x7, "|=" => 0x1, "&=" => 0x2, "^=" => 0x3, ">>=" => 0x6, _ => 0xE };
+                if !with_register(self, low)?
+                {
+                    return Err(self.error(&format!("'{}' takes a register", operator)));
+                }
+            },
+            _ => return Err(self.error(&format!("unknown operator '{}'", operator))),
+        }
+        Ok(())
+    }
+
+    // if ... then runs the next statement when the condition holds, if ...
+    // begin a block up to else or end
+    fn conditional(&mut self) -> Result<(), String>
+    {
+        let start = self.pos;
+        self.skip_condition()?;
+        let block = self.next()?;
+        let end = self.pos;
+        self.pos = start;
+
+        match block.as_str()
+        {
+            "then" => self.condition(false)?,
+            "begin" =>
+            {
+                self.condition(true)?;
+                self.blocks.push(self.here);
+                self.instruction(0x1000);
+            },
+            _ => return Err(self.error(&format!("expected 'then' or 'begin', found '{}'", block))),
+        }
+        self.pos = end;
+        Ok(())
+    }
+
+    fn skip_condition(&mut self) -> Result<(), String>
+    {
+        self.register()?;
+        let operator = self.next()?;
+        if operator != "key" && operator != "-key"
+        {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    // emits the instructions that skip the next one unless the condition
+    // holds, or unless it doesn't when `negate` is set. comparisons other
+    // than equality go through vF
+    fn condition(&mut self, negate: bool) -> Result<(), String>
+    {
+        let x = self.register()?;
+        let mut operator = self.next()?;
+        if negate
+        {
+            operator = match operator.as_str()
+            {
+                "==" => "!=", "!=" => "==",
+                "key" => "-key", "-key" => "key",
+                "<" => ">=", ">=" => "<",
+                ">" => "<=", "<=" => ">",
+                _ => return Err(self.error(&format!("unknown comparison '{}'", operator))),
+            }.to_string();
+        }
+
+        if operator == "key" || operator == "-key"
+        {
+            self.instruction(if operator == "key" { 0xE0A1 } else { 0xE09E } | x << 8);
+            return Ok(());
+        }
+
+        let register = if self.peek().is_some_and(|t| self.is_register(t)) { Some(self.register()?) } else { None };
+        let operand = match register { Some(y) => y, None => self.byte()? };
+
+        let temp = COMPARE_TEMP as u16;
+        let load_temp = |this: &mut Assembler|
+        {
+            match register
+            {
+                Some(y) => this.instruction(0x8000 | temp << 8 | y << 4),
+                None => this.instruction(0x6000 | temp << 8 | operand),
+            }
+        };
+
+        match operator.as_str()
+        {
+            "==" => self.instruction(match register { Some(y) => 0x9000 | x << 8 | y << 4, None => 0x4000 | x << 8 | operand }),
+            "!=" => self.instruction(match register { Some(y) => 0x5000 | x << 8 | y << 4, None => 0x3000 | x << 8 | operand }),
+
+            // vF := x - operand leaves the flag set when x >= operand
+            "<" | ">=" =>
+            {
+                load_temp(self);
+                self.instruction(0x8007 | temp << 8 | x << 4);
+                self.instruction(0x3000 | temp << 8 | if operator == "<" { 1 } else { 0 });
+            },
+
+            // vF := operand - x leaves the flag set when operand >= x
+            ">" | "<=" =>
+            {
+                load_temp(self);
+                self.instruction(0x8005 | temp << 8 | x << 4);
+                self.instruction(0x3000 | temp << 8 | if operator == ">" { 1 } else { 0 });
+            },
+            _ => return Err(self.error(&format!("unknown comparison '{}'", operator))),
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), String>
+    {
+        let name = self.next()?;
+        let mut arguments = Vec::new();
+        loop
+        {
+            let token = self.next()?;
+            if token == "{"
+            {
+                break;
+            }
+            arguments.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop
+        {
+            let token = self.next()?;
+            match token.as_str()
+            {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {},
+            }
+            if depth == 0
+            {
+                break;
+            }
+            body.push((token, self.line));
+        }
+
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    // replaces a macro call with its body, arguments substituted
+    fn expand(&mut self, name: &str) -> Result<(), String>
+    {
+        let count = self.macros[name].arguments.len();
+        let mut values = Vec::new();
+        for _ in 0..count
+        {
+            values.push(self.next()?);
+        }
+
+        let definition = &self.macros[name];
+        let body: Vec<(String, usize)> = definition.body.iter().map(|(token, line)|
+        {
+            match definition.arguments.iter().position(|a| a == token)
+            {
+                Some(i) => (values[i].clone(), *line),
+                None => (token.clone(), *line),
+            }
+        }).collect();
+
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+
+    // the expression up to the closing brace. operators have no precedence
+    // and are applied right to left, as in Octo: 2 * 3 + 1 is 8
+    fn calc_block(&mut self) -> Result<f64, String>
+    {
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn expression(&mut self) -> Result<f64, String>
+    {
+        let left = self.term()?;
+        let operator = match self.peek()
+        {
+            Some(operator) if is_binary(operator) => operator.to_string(),
+            _ => return Ok(left),
+        };
+        self.next()?;
+        let right = self.expression()?;
+
+        let (a, b) = (left as i64, right as i64);
+        Ok(match operator.as_str()
+        {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            _ => (left != right) as i64 as f64,
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, String>
+    {
+        let token = self.next()?;
+        Ok(match token.as_str()
+        {
+            "(" =>
+            {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            },
+            "-" => -self.term()?,
+            "~" => !(self.term()? as i64) as f64,
+            "!" => (self.term()? == 0.0) as i64 as f64,
+            "abs" => self.term()?.abs(),
+            "floor" => self.term()?.floor(),
+            "ceil" => self.term()?.ceil(),
+            "sqrt" => self.term()?.sqrt(),
+            "sin" => self.term()?.sin(),
+            "cos" => self.term()?.cos(),
+            "@" =>
+            {
+                let address = self.term()? as usize;
+                self.rom[address % self.rom.len()] as f64
+            },
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            "HERE" => self.here as f64,
+            _ => self.lookup(&token).ok_or_else(|| self.error(&format!("undefined value '{}'", token)))?,
+        })
+    }
+
+    // resolves the forward references and the jump to main
+    fn finish(mut self) -> Result<Vec<u8>, String>
+    {
+        if !self.blocks.is_empty() || !self.loops.is_empty()
+        {
+            return Err(self.error("missing end or again"));
+        }
+
+        let main = *self.labels.get("main").ok_or("the program has no main label")?;
+        self.patch(PROGRAM_START, main);
+
+        for (address, label, fixup, line) in std::mem::take(&mut self.fixups)
+        {
+            let target = *self.labels.get(&label).ok_or_else(|| format!("line {}: undefined label '{}'", line, label))?;
+            match fixup
+            {
+                Fixup::Address =>
+                {
+                    if target > 0xFFF
+                    {
+                        return Err(format!("line {}: label '{}' is out of reach at 0x{:X}", line, label, target));
+                    }
+                    self.rom[address] |= (target >> 8) as u8;
+                    self.rom[address + 1] = target as u8;
+                },
+                Fixup::Long =>
+                {
+                    self.rom[address] = (target >> 8) as u8;
+                    self.rom[address + 1] = target as u8;
+                },
+                Fixup::Unpack(nibble) =>
+                {
+                    self.rom[address + 1] = nibble << 4 | (target >> 8) as u8 & 0x0F;
+                    self.rom[address + 3] = target as u8;
+                },
+            }
+        }
+
+        Ok(self.rom[PROGRAM_START..self.end].to_vec())
+    }
+}
+
+fn register_number(text: &str) -> Option<u8>
+{
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next())
+    {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+fn is_name(text: &str) -> bool
+{
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_') && !text.starts_with('"')
+}
+
+fn is_binary(text: &str) -> bool
+{
+    ["+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=", "==", "!="].contains(&text)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn jump_to_main()
+    {
+        assert_eq!(assemble(": main v0 := 5 jump main").unwrap(), [0x12, 0x02, 0x60, 0x05, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn constants_aliases_and_loops()
+    {
+        let source = ":const SPEED 3 :alias x v2
+                      : main x := SPEED x += 1
+                      if x == 4 then v1 := 0
+                      loop x -= v1 if x != 0 then again";
+        assert_eq!(assemble(source).unwrap(),
+                   [0x12, 0x02, 0x62, 0x03, 0x72, 0x01, 0x42, 0x04, 0x61, 0x00, 0x82, 0x15, 0x32, 0x00, 0x12, 0x0A]);
+    }
+
+    #[test]
+    fn comparison_blocks()
+    {
+        // vF := v4 - v3 borrows when v3 > v4
+        assert_eq!(assemble(": main if v3 > v4 begin v0 := 1 else v0 := 2 end").unwrap(),
+                   [0x12, 0x02, 0x8F, 0x40, 0x8F, 0x35, 0x3F, 0x00, 0x12, 0x0E, 0x60, 0x01, 0x12, 0x10, 0x60, 0x02]);
+    }
+
+    #[test]
+    fn macros_calc_and_data()
+    {
+        let source = ":macro twice A { A A }
+                      : main twice clear
+                      :calc half { 10 / 2 } v0 := half
+                      i := sprite
+                      : sprite 0xF0 0b10010000";
+        assert_eq!(assemble(source).unwrap(), [0x12, 0x02, 0x00, 0xE0, 0x00, 0xE0, 0x60, 0x05, 0xA2, 0x0A, 0xF0, 0x90]);
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(assemble(": main v0 := 300").is_err());
+        assert!(assemble(": main jump nowhere").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+pub const USAGE: &str = "\
+syntax: dale8 [options] [rom_file]
+
+the rom file can be a zip archive, or - to read it from stdin
+
+options:
+  --entry <name>        ROM to run from an archive holding several
+  --config <file>       read settings from the given file instead of dale8.cfg
+  --ipf <n>             instructions executed per 60 Hz frame
+  --speed <x>           emulation speed, e.g. 0.5 or 2
+  --timing <model>      uniform (ipf instructions per frame) or vip (the
+                        machine cycles of the COSMAC VIP interpreter)
+  --platform <name>     quirks of chip-8, vip, schip, xo-chip or auto to
+                        pick them from the instructions the ROM uses;
+                        without it dale8 keeps its own quirks
+  --layout <name>       memory layout: default, modern (font at 0x050), vip,
+                        eti-660 (programs at 0x600) or dream6800
+  --font <name>         small font: chip48, vip, dream6800, eti660, fishnchips
+                        or a file of 80 bytes, optionally followed by the
+                        160 bytes of the big font
+  --scan                print the instructions the ROM uses and the
+                        recommended platform, then exit
+  --vip                 run the ROM on an emulated COSMAC VIP with the
+                        interpreter and monitor of [vip], comparing its
+                        display with dale8 frame by frame. implies
+                        --platform vip --layout vip --timing vip
+  --vsync               synchronize frames with the display refresh
+  --filters <list>      post-processing filters: scanlines, grid, bloom and
+                        curvature, e.g. scanlines,bloom
+  --palette <palette>   display palette: classic, green, amber, lcd, octo or
+                        a list of colours such as #000000,#33FF66
+  --persistence <mode>  frame blending against flicker: off, two-frame or a
+                        decay factor between 0 and 1
+  --scale <n>           initial window size as a multiple of the framebuffer
+  --scaling <mode>      integer (whole multiples only) or fit (fill the window)
+  --wav <file>          capture the sound output to a WAV file
+  --headless <frames>   run the given number of frames without opening a window
+  --screenshot <file>   save the final frame of a headless run as PNG
+  --heatmap <file>      save the memory access heatmap at the end of a
+                        headless run as PNG
+  --coverage <file>     write an execution coverage report on exit, as HTML
+                        if the file name ends in .html and as text otherwise
+  --profile <file>      write a profile on exit: folded stacks for flamegraph
+                        tools if the file name ends in .folded, hotspots and
+                        subroutines otherwise
+  --symbols <file>      label and source line addresses for the coverage
+                        and profile reports";
+
+pub struct Options
+{
+    pub rom: String,
+    pub entry: Option<String>,
+    pub config: Option<String>,
+    pub ipf: Option<u32>,
+    pub speed: Option<f64>,
+    pub timing: Option<String>,
+    pub platform: Option<String>,
+    pub layout: Option<String>,
+    pub font: Option<String>,
+    pub scan: bool,
+    pub vip: bool,
+    pub vsync: bool,
+    pub filters: Option<String>,
+    pub palette: Option<String>,
+    pub persistence: Option<String>,
+    pub scale: Option<u32>,
+    pub scaling: Option<String>,
+    pub wav: Option<String>,
+    pub headless: Option<u32>,
+    pub screenshot: Option<String>,
+    pub heatmap: Option<String>,
+    pub coverage: Option<String>,
+    pub profile: Option<String>,
+    pub symbols: Option<String>,
+}
+
+pub fn parse(args: &[String]) -> Result<Options, String>
+{
+    let mut rom = None;
+    let mut entry = None;
+    let mut config = None;
+    let mut ipf = None;
+    let mut speed = None;
+    let mut timing = None;
+    let mut platform = None;
+    let mut layout = None;
+    let mut font = None;
+    let mut scan = false;
+    let mut vip = false;
+    let mut vsync = false;
+    let mut filters = None;
+    let mut palette = None;
+    let mut persistence = None;
+    let mut scale = None;
+    let mut scaling = None;
+    let mut wav = None;
+    let mut headless = None;
+    let mut screenshot = None;
+    let mut heatmap = None;
+    let mut coverage = None;
+    let mut profile = None;
+    let mut symbols = None;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--entry" => entry = Some(value(arg, args.next())?.to_string()),
+            "--config" => config = Some(value(arg, args.next())?.to_string()),
+            "--ipf" => ipf = Some(number(arg, args.next())?),
+            "--speed" => speed = Some(number(arg, args.next())?),
+            "--timing" => timing = Some(value(arg, args.next())?.to_string()),
+            "--platform" => platform = Some(value(arg, args.next())?.to_string()),
+            "--layout" => layout = Some(value(arg, args.next())?.to_string()),
+            "--font" => font = Some(value(arg, args.next())?.to_string()),
+            "--scan" => scan = true,
+            "--vip" => vip = true,
+            "--vsync" => vsync = true,
+            "--filters" => filters = Some(value(arg, args.next())?.to_string()),
+            "--palette" => palette = Some(value(arg, args.next())?.to_string()),
+            "--persistence" => persistence = Some(value(arg, args.next())?.to_string()),
+            "--scale" => scale = Some(number(arg, args.next())?),
+            "--scaling" => scaling = Some(value(arg, args.next())?.to_string()),
+            "--wav" => wav = Some(value(arg, args.next())?.to_string()),
+            "--headless" => headless = Some(number(arg, args.next())?),
+            "--screenshot" => screenshot = Some(value(arg, args.next())?.to_string()),
+            "--heatmap" => heatmap = Some(value(arg, args.next())?.to_string()),
+            "--coverage" => coverage = Some(value(arg, args.next())?.to_string()),
+            "--profile" => profile = Some(value(arg, args.next())?.to_string()),
+            "--symbols" => symbols = Some(value(arg, args.next())?.to_string()),
+
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+
+            _ =>
+            {
+                if rom.is_some()
+                {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                rom = Some(arg.clone());
+            }
+        }
+    }
+
+    // both machines have to run the same interpreter at the same speed
+    if vip
+    {
+        platform = platform.or_else(|| Some("vip".to_string()));
+        layout = layout.or_else(|| Some("vip".to_string()));
+        timing = timing.or_else(|| Some("vip".to_string()));
+    }
+
+    Ok(Options
+    {
+        rom: rom.ok_or("no rom file given")?,
+        entry,
+        config,
+        ipf,
+        speed,
+        timing,
+        platform,
+        layout,
+        font,
+        scan,
+        vip,
+        vsync,
+        filters,
+        palette,
+        persistence,
+        scale,
+        scaling,
+        wav,
+        headless,
+        screenshot,
+        heatmap,
+        coverage,
+        profile,
+        symbols,
+    })
+}
+
+fn value<'a>(option: &str, value: Option<&'a String>) -> Result<&'a str, String>
+{
+    value.map(|v| v.as_str()).ok_or(format!("{} expects a value", option))
+}
+
+fn number<T: std::str::FromStr>(option: &str, arg: Option<&String>) -> Result<T, String>
+{
+    let text = value(option, arg)?;
+    text.parse().map_err(|_| format!("{}: invalid number {}", option, text))
+}
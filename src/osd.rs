@@ -0,0 +1,201 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::time::{Duration, Instant};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, RenderTarget};
+
+use crate::scheduler::Scheduler;
+
+pub const GLYPH_WIDTH: i32 = 3;
+pub const GLYPH_HEIGHT: i32 = 5;
+
+// 3x5 font, one byte per row with the pixels in the low three bits
+const FONT: [(char, [u8; 5]); 50] =
+[
+    ('0', [7, 5, 5, 5, 7]), ('1', [2, 6, 2, 2, 7]), ('2', [7, 1, 7, 4, 7]), ('3', [7, 1, 3, 1, 7]),
+    ('4', [5, 5, 7, 1, 1]), ('5', [7, 4, 7, 1, 7]), ('6', [7, 4, 7, 5, 7]), ('7', [7, 1, 2, 2, 2]),
+    ('8', [7, 5, 7, 5, 7]), ('9', [7, 5, 7, 1, 7]),
+    ('A', [2, 5, 7, 5, 5]), ('B', [6, 5, 6, 5, 6]), ('C', [3, 4, 4, 4, 3]), ('D', [6, 5, 5, 5, 6]),
+    ('E', [7, 4, 6, 4, 7]), ('F', [7, 4, 6, 4, 4]), ('G', [3, 4, 5, 5, 3]), ('H', [5, 5, 7, 5, 5]),
+    ('I', [7, 2, 2, 2, 7]), ('J', [1, 1, 1, 5, 2]), ('K', [5, 5, 6, 5, 5]), ('L', [4, 4, 4, 4, 7]),
+    ('M', [5, 7, 7, 5, 5]), ('N', [6, 5, 5, 5, 5]), ('O', [2, 5, 5, 5, 2]), ('P', [6, 5, 6, 4, 4]),
+    ('Q', [2, 5, 5, 6, 3]), ('R', [6, 5, 6, 5, 5]), ('S', [3, 4, 2, 1, 6]), ('T', [7, 2, 2, 2, 2]),
+    ('U', [5, 5, 5, 5, 7]), ('V', [5, 5, 5, 5, 2]), ('W', [5, 5, 7, 7, 5]), ('X', [5, 5, 2, 5, 5]),
+    ('Y', [5, 5, 2, 2, 2]), ('Z', [7, 1, 2, 4, 7]),
+    ('.', [0, 0, 0, 0, 2]), (',', [0, 0, 0, 2, 4]), (':', [0, 2, 0, 2, 0]), ('-', [0, 0, 7, 0, 0]),
+    ('+', [0, 2, 7, 2, 0]), ('/', [1, 1, 2, 4, 4]), ('%', [5, 1, 2, 4, 5]), ('!', [2, 2, 2, 0, 2]),
+    ('?', [7, 1, 3, 0, 2]), ('(', [1, 2, 2, 2, 1]), (')', [4, 2, 2, 2, 4]), ('[', [3, 2, 2, 2, 3]),
+    (']', [6, 2, 2, 2, 6]), ('=', [0, 7, 0, 7, 0]),
+];
+
+// how long messages stay on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+const TEXT_COLOR: Color = Color { r: 0xFF, g: 0xFF, b: 0xFF, a: 0xFF };
+const ERROR_COLOR: Color = Color { r: 0xFF, g: 0x40, b: 0x40, a: 0xFF };
+const SHADOW_COLOR: Color = Color { r: 0x00, g: 0x00, b: 0x00, a: 0xFF };
+
+fn glyph(c: char) -> Option<&'static [u8; 5]>
+{
+    let c = c.to_ascii_uppercase();
+    FONT.iter().find(|g| g.0 == c).map(|g| &g.1)
+}
+
+// draws a line of text with its top-left corner at (x, y), every font pixel
+// being a square of `scale` pixels. unknown characters are left blank
+pub fn draw_text<T: RenderTarget>(canvas: &mut Canvas<T>, x: i32, y: i32, scale: u32, text: &str, color: Color)
+{
+    let mut rects = Vec::new();
+    let size = scale as i32;
+
+    for (i, c) in text.chars().enumerate()
+    {
+        if let Some(rows) = glyph(c)
+        {
+            let left = x + i as i32 * (GLYPH_WIDTH + 1) * size;
+            for (row, bits) in rows.iter().enumerate()
+            {
+                for column in 0..GLYPH_WIDTH
+                {
+                    if bits & (4 >> column) != 0
+                    {
+                        rects.push(Rect::new(left + column * size, y + row as i32 * size, scale, scale));
+                    }
+                }
+            }
+        }
+    }
+
+    canvas.set_draw_color(color);
+    canvas.fill_rects(&rects).ok();
+}
+
+// on-screen display drawn over the game: frame rate, emulation speed and
+// state in the top-left corner, messages in the bottom-left corner
+pub struct Osd
+{
+    pub visible: bool,
+    messages: Vec<(String, Color, Instant)>,
+
+    // measurement over the last second
+    sample_start: Instant,
+    sample_frames: u64,
+    sample_instructions: u64,
+    fps: u64,
+    ips: u64,
+}
+
+impl Osd
+{
+    pub fn new() -> Osd
+    {
+        Osd
+        {
+            visible: false,
+            messages: Vec::new(),
+
+            sample_start: Instant::now(),
+            sample_frames: 0,
+            sample_instructions: 0,
+            fps: 0,
+            ips: 0,
+        }
+    }
+
+    pub fn message(&mut self, text: &str)
+    {
+        self.messages.push((text.to_string(), TEXT_COLOR, Instant::now()));
+    }
+
+    pub fn error(&mut self, text: &str)
+    {
+        self.messages.push((text.to_string(), ERROR_COLOR, Instant::now()));
+    }
+
+    // true while something is shown and the screen has to be redrawn
+    pub fn active(&self) -> bool
+    {
+        self.visible || !self.messages.is_empty()
+    }
+
+    // samples the counters, call once per host frame
+    pub fn update(&mut self, scheduler: &Scheduler)
+    {
+        let elapsed = self.sample_start.elapsed();
+        if elapsed >= Duration::from_secs(1)
+        {
+            let seconds = elapsed.as_secs_f64();
+            self.fps = ((scheduler.frames - self.sample_frames) as f64 / seconds).round() as u64;
+            self.ips = ((scheduler.instructions - self.sample_instructions) as f64 / seconds).round() as u64;
+
+            self.sample_start = Instant::now();
+            self.sample_frames = scheduler.frames;
+            self.sample_instructions = scheduler.instructions;
+        }
+
+        self.messages.retain(|m| m.2.elapsed() < MESSAGE_DURATION);
+    }
+
+    pub fn draw<T: RenderTarget>(&self, canvas: &mut Canvas<T>, scheduler: &Scheduler)
+    {
+        let (_, height) = canvas.output_size().unwrap_or((0, 0));
+        let scale = (height / 160).max(2);
+        let line = (GLYPH_HEIGHT + 2) * scale as i32;
+        let margin = scale as i32 * 2;
+
+        if self.visible
+        {
+            let mut status = vec![format!("FPS {}", self.fps), format!("IPS {}", self.ips)];
+            if scheduler.paused
+            {
+                status.push("PAUSED".to_string());
+            }
+            else if scheduler.fast_forward
+            {
+                match scheduler.fast_forward_speed
+                {
+                    Some(speed) => status.push(format!("FAST FORWARD {}X", speed)),
+                    None => status.push("FAST FORWARD".to_string()),
+                }
+            }
+            else if scheduler.speed != 1.0
+            {
+                status.push(format!("SPEED {}X", scheduler.speed));
+            }
+            if scheduler.dropped_frames > 0
+            {
+                status.push(format!("DROPPED {}", scheduler.dropped_frames));
+            }
+
+            for (i, text) in status.iter().enumerate()
+            {
+                shadowed(canvas, margin, margin + i as i32 * line, scale, text, TEXT_COLOR);
+            }
+        }
+
+        let bottom = height as i32 - margin - GLYPH_HEIGHT * scale as i32;
+        for (i, message) in self.messages.iter().rev().enumerate()
+        {
+            shadowed(canvas, margin, bottom - i as i32 * line, scale, &message.0, message.1);
+        }
+    }
+}
+
+// text with a drop shadow so it stays readable on any palette
+fn shadowed<T: RenderTarget>(canvas: &mut Canvas<T>, x: i32, y: i32, scale: u32, text: &str, color: Color)
+{
+    draw_text(canvas, x + scale as i32, y + scale as i32, scale, text, SHADOW_COLOR);
+    draw_text(canvas, x, y, scale, text, color);
+}
@@ -0,0 +1,141 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::config::{self, Config};
+
+pub type Rgb = [u8; 3];
+
+// colours indexed by pixel value: background, first plane, second plane and
+// both planes lit. plain CHIP-8 only ever uses the first two
+#[derive(Clone)]
+pub struct Palette
+{
+    pub name: String,
+    pub colors: [Rgb; 4],
+}
+
+const BUILTIN: [(&str, [Rgb; 4]); 5] =
+[
+    ("classic", [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]]),
+    ("green",   [[0x00, 0x14, 0x00], [0x33, 0xFF, 0x66], [0x1A, 0x99, 0x3D], [0x0D, 0x4D, 0x1F]]),
+    ("amber",   [[0x14, 0x0A, 0x00], [0xFF, 0xB0, 0x00], [0xB3, 0x7A, 0x00], [0x66, 0x44, 0x00]]),
+    ("lcd",     [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]]),
+    ("octo",    [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]]),
+];
+
+impl Palette
+{
+    pub fn color(&self, pixel: u8) -> Rgb
+    {
+        self.colors[(pixel & 3) as usize]
+    }
+}
+
+// the palettes F2 cycles through: the built-in ones followed by the ones
+// defined in the [palettes] section, e.g.
+//
+//   [palettes]
+//   paper = #F0F0E0, #202020
+//
+//   [display]
+//   palette = paper
+pub struct Palettes
+{
+    list: Vec<Palette>,
+    current: usize,
+}
+
+impl Palettes
+{
+    // `choice` (from the command line) takes precedence over the configuration
+    // and is either a palette name or a list of colours
+    pub fn new(config: &Config, choice: Option<&str>) -> Result<Palettes, String>
+    {
+        let mut list: Vec<Palette> = BUILTIN.iter()
+            .map(|&(name, colors)| Palette { name: name.to_string(), colors })
+            .collect();
+
+        if let Some(section) = config.section("palettes")
+        {
+            for (name, value) in &section.entries
+            {
+                let colors = parse_colors(value).map_err(|e| format!("[palettes] {}: {}", name, e))?;
+                list.retain(|p| &p.name != name);
+                list.push(Palette { name: name.clone(), colors });
+            }
+        }
+
+        let mut palettes = Palettes { list, current: 0 };
+
+        if let Some(choice) = choice.or_else(|| config.get("display", "palette"))
+        {
+            let choice = choice.to_lowercase();
+            match palettes.list.iter().position(|p| p.name == choice)
+            {
+                Some(pos) => palettes.current = pos,
+                None =>
+                {
+                    let colors = parse_colors(&choice).map_err(|e| format!("palette {}: {}", choice, e))?;
+                    palettes.list.push(Palette { name: "custom".to_string(), colors });
+                    palettes.current = palettes.list.len() - 1;
+                }
+            }
+        }
+        Ok(palettes)
+    }
+
+    pub fn current(&self) -> &Palette
+    {
+        &self.list[self.current]
+    }
+
+    pub fn next(&mut self) -> &Palette
+    {
+        self.current = (self.current + 1) % self.list.len();
+        self.current()
+    }
+}
+
+// two colours (background, foreground) or all four, as #RRGGBB
+fn parse_colors(value: &str) -> Result<[Rgb; 4], String>
+{
+    let colors = config::list(value).iter().map(|c| parse_color(c)).collect::<Result<Vec<_>, _>>()?;
+
+    match colors.len()
+    {
+        // derive the extra planes from the foreground
+        2 => Ok([colors[0], colors[1], blend(colors[0], colors[1]), blend(colors[0], colors[1])]),
+        4 => Ok([colors[0], colors[1], colors[2], colors[3]]),
+        _ => Err("expected 2 or 4 colours".to_string()),
+    }
+}
+
+pub fn parse_color(text: &str) -> Result<Rgb, String>
+{
+    let hex = text.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour {}", text))?;
+    if hex.len() != 6
+    {
+        return Err(format!("invalid colour {}", text));
+    }
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+fn blend(a: Rgb, b: Rgb) -> Rgb
+{
+    [
+        ((a[0] as u16 + b[0] as u16) / 2) as u8,
+        ((a[1] as u16 + b[1] as u16) / 2) as u8,
+        ((a[2] as u16 + b[2] as u16) / 2) as u8,
+    ]
+}
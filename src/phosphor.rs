@@ -0,0 +1,155 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::palette::{Palette, Rgb};
+
+// decay used when switching to the fading mode from the keyboard
+const DEFAULT_DECAY: f32 = 0.6;
+
+// how the displayed image follows the VM's framebuffer. games XOR sprites
+// away and back every frame, so showing the framebuffer as-is flickers
+#[derive(Clone, Copy, PartialEq)]
+pub enum Persistence
+{
+    Off,
+    TwoFrames,  // a pixel is lit if it was lit in either of the last two frames
+    Decay(f32), // lit pixels fade out, keeping this fraction of their brightness each frame
+}
+
+impl Persistence
+{
+    // "off", "two-frame" or a decay factor between 0 and 1
+    pub fn parse(text: &str) -> Result<Persistence, String>
+    {
+        match text
+        {
+            "off" => Ok(Persistence::Off),
+            "two-frame" => Ok(Persistence::TwoFrames),
+            _ => match text.parse::<f32>()
+            {
+                Ok(decay) if (0.0..1.0).contains(&decay) => Ok(Persistence::Decay(decay)),
+                _ => Err(format!("invalid persistence {}", text)),
+            },
+        }
+    }
+
+    pub fn next(self) -> Persistence
+    {
+        match self
+        {
+            Persistence::Off => Persistence::TwoFrames,
+            Persistence::TwoFrames => Persistence::Decay(DEFAULT_DECAY),
+            Persistence::Decay(_) => Persistence::Off,
+        }
+    }
+
+    pub fn name(self) -> String
+    {
+        match self
+        {
+            Persistence::Off => "off".to_string(),
+            Persistence::TwoFrames => "two-frame".to_string(),
+            Persistence::Decay(decay) => format!("decay {}", decay),
+        }
+    }
+}
+
+// blends successive frames of the VM's framebuffer, leaving the VM untouched
+pub struct Phosphor
+{
+    pub mode: Persistence,
+    current: Vec<u8>,  // framebuffer as of the last update
+    previous: Vec<u8>, // framebuffer of the frame before
+    levels: Vec<f32>,  // brightness of each pixel
+    lit: Vec<u8>,      // value each pixel had when it was last lit
+}
+
+impl Phosphor
+{
+    pub fn new(mode: Persistence, size: usize) -> Phosphor
+    {
+        Phosphor
+        {
+            mode,
+            current: vec![0; size],
+            previous: vec![0; size],
+            levels: vec![0.0; size],
+            lit: vec![0; size],
+        }
+    }
+
+    // advances by one frame, returns true if the displayed image may differ
+    // from the framebuffer and needs to be redrawn even without a draw
+    pub fn update(&mut self, gfx: &[u8]) -> bool
+    {
+        let mut fading = false;
+
+        self.previous.copy_from_slice(&self.current);
+        self.current.copy_from_slice(gfx);
+
+        match self.mode
+        {
+            Persistence::Off => {},
+
+            Persistence::TwoFrames =>
+            {
+                fading = self.previous != self.current;
+            },
+
+            Persistence::Decay(decay) =>
+            {
+                for (i, &pixel) in gfx.iter().enumerate()
+                {
+                    if pixel != 0
+                    {
+                        self.levels[i] = 1.0;
+                        self.lit[i] = pixel;
+                    }
+                    else if self.levels[i] > 0.0
+                    {
+                        self.levels[i] *= decay;
+                        if self.levels[i] < 1.0 / 255.0
+                        {
+                            self.levels[i] = 0.0;
+                        }
+                        fading = true;
+                    }
+                }
+            },
+        }
+        fading
+    }
+
+    // writes the RGB24 image of the current frame
+    pub fn render(&self, gfx: &[u8], width: usize, palette: &Palette, buffer: &mut [u8], pitch: usize)
+    {
+        for (i, &pixel) in gfx.iter().enumerate()
+        {
+            let color = match self.mode
+            {
+                Persistence::Off => palette.color(pixel),
+                Persistence::TwoFrames => palette.color(pixel.max(self.previous[i])),
+                Persistence::Decay(_) => mix(palette.color(0), palette.color(self.lit[i]), self.levels[i]),
+            };
+
+            let offset = (i / width) * pitch + (i % width) * 3;
+            buffer[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+fn mix(from: Rgb, to: Rgb, amount: f32) -> Rgb
+{
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+    [channel(from[0], to[0]), channel(from[1], to[1]), channel(from[2], to[2])]
+}
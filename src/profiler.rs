@@ -0,0 +1,233 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::coverage::Symbols;
+use crate::disasm;
+
+// addresses listed in the hotspot table
+const HOTSPOTS: usize = 20;
+
+#[derive(Default)]
+struct Routine
+{
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+// a subroutine on the call stack: its entry point, the cycle count when it
+// was called and the interned stack it belongs to
+struct Frame
+{
+    entry: u16,
+    start: u64,
+    stack: usize,
+}
+
+// cycles spent per address and per subroutine. calls are followed through
+// 2NNN and 00EE, code outside any subroutine belongs to the program entry
+pub struct Profiler
+{
+    cycles: Vec<u64>,
+    total: u64,
+
+    routines: BTreeMap<u16, Routine>,
+    frames: Vec<Frame>,
+
+    // every distinct call stack gets an index, the folded output needs the
+    // cycles spent in each of them
+    stacks: HashMap<Vec<u16>, usize>,
+    stack_cycles: Vec<u64>,
+}
+
+impl Profiler
+{
+    pub fn new(size: usize, entry: u16) -> Profiler
+    {
+        let mut profiler = Profiler
+        {
+            cycles: vec![0; size],
+            total: 0,
+
+            routines: BTreeMap::new(),
+            frames: Vec::new(),
+
+            stacks: HashMap::new(),
+            stack_cycles: Vec::new(),
+        };
+        profiler.call(entry);
+        profiler
+    }
+
+    // called by the VM after executing `opcode` at `pc`, which wraps around
+    // memory like the fetch does
+    pub fn record(&mut self, pc: u16, opcode: u16, cycles: u64)
+    {
+        let at = pc as usize % self.cycles.len();
+        self.cycles[at] += cycles;
+        self.total += cycles;
+
+        let frame = self.frames.last().unwrap();
+        self.stack_cycles[frame.stack] += cycles;
+        self.routines.get_mut(&frame.entry).unwrap().exclusive += cycles;
+
+        if opcode & 0xF000 == 0x2000
+        {
+            self.call(opcode & 0x0FFF);
+        }
+        else if opcode == 0x00EE && self.frames.len() > 1
+        {
+            let frame = self.frames.pop().unwrap();
+
+            // recursive calls are already counted by the outermost one
+            if !self.frames.iter().any(|f| f.entry == frame.entry)
+            {
+                self.routines.get_mut(&frame.entry).unwrap().inclusive += self.total - frame.start;
+            }
+        }
+    }
+
+    fn call(&mut self, entry: u16)
+    {
+        let mut path: Vec<u16> = self.frames.iter().map(|f| f.entry).collect();
+        path.push(entry);
+
+        let next = self.stacks.len();
+        let stack = *self.stacks.entry(path).or_insert(next);
+        if stack == next
+        {
+            self.stack_cycles.push(0);
+        }
+
+        self.routines.entry(entry).or_default().calls += 1;
+        self.frames.push(Frame { entry, start: self.total, stack });
+    }
+
+    // inclusive cycles, counting the calls still running as if they
+    // returned now
+    fn inclusive(&self, entry: u16) -> u64
+    {
+        let running = self.frames.iter().find(|f| f.entry == entry).map_or(0, |f| self.total - f.start);
+        self.routines[&entry].inclusive + running
+    }
+
+    // writes folded stacks (one "main;sub_2A4;sub_300 1234" line per call
+    // stack, as read by flamegraph tools) if the file name ends in .folded,
+    // a text report with hotspots and subroutines otherwise
+    pub fn write_report(&self, filename: &str, memory: &[u8], symbols: Option<&Symbols>) -> Result<(), String>
+    {
+        let report = if filename.to_lowercase().ends_with(".folded")
+        {
+            self.folded(symbols)
+        }
+        else
+        {
+            self.report(memory, symbols)
+        };
+
+        fs::write(filename, report).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    fn folded(&self, symbols: Option<&Symbols>) -> String
+    {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .filter(|(_, &index)| self.stack_cycles[index] > 0)
+            .map(|(path, &index)|
+            {
+                let names: Vec<String> = path.iter().map(|&a| name(a, symbols)).collect();
+                format!("{} {}\n", names.join(";"), self.stack_cycles[index])
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    fn report(&self, memory: &[u8], symbols: Option<&Symbols>) -> String
+    {
+        let mut report = format!("total cycles: {}\n\nhotspots\n\n", self.total);
+        report += "    cycles       %  address  instruction\n";
+
+        let mut hotspots: Vec<usize> = (0..self.cycles.len()).filter(|&a| self.cycles[a] > 0).collect();
+        hotspots.sort_by(|&a, &b| self.cycles[b].cmp(&self.cycles[a]).then(a.cmp(&b)));
+        for &address in hotspots.iter().take(HOTSPOTS)
+        {
+            let opcode = (memory[address] as u16) << 8 | memory[(address + 1) % memory.len()] as u16;
+            report += &format!("{:>10} {:>6.2}  {:03X}      {}\n", self.cycles[address], self.percent(self.cycles[address]),
+                address, disasm::disassemble(opcode));
+        }
+
+        report += "\nsubroutines\n\n";
+        report += "     calls   inclusive       %   exclusive       %  name\n";
+
+        let mut routines: Vec<u16> = self.routines.keys().cloned().collect();
+        routines.sort_by(|&a, &b| self.inclusive(b).cmp(&self.inclusive(a)).then(a.cmp(&b)));
+        for entry in routines
+        {
+            let routine = &self.routines[&entry];
+            let inclusive = self.inclusive(entry);
+            report += &format!("{:>10}  {:>10} {:>6.2}  {:>10} {:>6.2}  {}\n", routine.calls, inclusive, self.percent(inclusive),
+                routine.exclusive, self.percent(routine.exclusive), name(entry, symbols));
+        }
+
+        report
+    }
+
+    fn percent(&self, cycles: u64) -> f64
+    {
+        if self.total == 0 { 0.0 } else { cycles as f64 * 100.0 / self.total as f64 }
+    }
+}
+
+fn name(address: u16, symbols: Option<&Symbols>) -> String
+{
+    match symbols.and_then(|s| s.label(address))
+    {
+        Some(label) => label.to_string(),
+        None => format!("sub_{:03X}", address),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn subroutines()
+    {
+        // 200: call 300, 300: return, 202: loop
+        let mut profiler = Profiler::new(0x1000, 0x200);
+        profiler.record(0x200, 0x2300, 1);
+        profiler.record(0x300, 0x6001, 1);
+        profiler.record(0x302, 0x00EE, 1);
+        profiler.record(0x202, 0x1202, 1);
+
+        assert_eq!(profiler.total, 4);
+        assert_eq!(profiler.routines[&0x300].calls, 1);
+        assert_eq!((profiler.inclusive(0x300), profiler.routines[&0x300].exclusive), (2, 2));
+        assert_eq!((profiler.inclusive(0x200), profiler.routines[&0x200].exclusive), (4, 2));
+        assert_eq!(profiler.folded(None), "sub_200 2\nsub_200;sub_300 2\n");
+    }
+
+    #[test]
+    fn past_the_end_of_memory()
+    {
+        // the fetch wraps around a 4K layout, so does the count
+        let mut profiler = Profiler::new(0x1000, 0x200);
+        profiler.record(0x1002, 0x6001, 3);
+        assert_eq!(profiler.cycles[0x002], 3);
+    }
+}
@@ -0,0 +1,154 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::config::Config;
+use crate::filters::{self, Filter, Image};
+use crate::options::Options;
+use crate::palette::Palettes;
+use crate::phosphor::{Persistence, Phosphor};
+use crate::png;
+
+// upscaling applied before filtering, when no filter_scale is configured
+const DEFAULT_FILTER_SCALE: usize = 4;
+
+// turns the VM's framebuffer into the image shown on screen: palette,
+// persistence and post-processing filters. shared by the window and the
+// headless screenshot exporter so both produce the same pixels
+pub struct Renderer
+{
+    pub palettes: Palettes,
+    pub phosphor: Phosphor,
+    pub filters: Vec<Filter>,
+    filter_scale: usize,
+    rotation: u32, // clockwise, in quarter turns
+
+    frame: Image,
+    scaled: Image,
+    rotated: Image,
+}
+
+impl Renderer
+{
+    pub fn new(config: &Config, options: &Options, gfx_size: usize) -> Result<Renderer, String>
+    {
+        let palettes = Palettes::new(config, options.palette.as_deref())?;
+
+        let persistence = options.persistence.as_deref().or_else(|| config.get("display", "persistence")).unwrap_or("off");
+        let phosphor = Phosphor::new(Persistence::parse(persistence)?, gfx_size);
+
+        let filters = match options.filters.as_deref().or_else(|| config.get("display", "filters"))
+        {
+            Some(list) => filters::parse(list)?,
+            None => Vec::new(),
+        };
+
+        let filter_scale = match config.get("display", "filter_scale")
+        {
+            Some(scale) => scale.parse::<usize>().map_err(|_| format!("[display]: invalid filter_scale {}", scale))?.max(1),
+            None => DEFAULT_FILTER_SCALE,
+        };
+
+        // for programs made for a display turned on its side
+        let rotation = match config.value::<u32>("display", "rotation")?.unwrap_or(0)
+        {
+            degrees @ 0 | degrees @ 90 | degrees @ 180 | degrees @ 270 => degrees / 90,
+            degrees => return Err(format!("[display]: invalid rotation {}", degrees)),
+        };
+
+        Ok(Renderer
+        {
+            palettes,
+            phosphor,
+            filters,
+            filter_scale,
+            rotation,
+
+            frame: Image::new(0, 0),
+            scaled: Image::new(0, 0),
+            rotated: Image::new(0, 0),
+        })
+    }
+
+    // advances the persistence by one frame, true if a redraw is needed
+    pub fn update(&mut self, gfx: &[u8]) -> bool
+    {
+        self.phosphor.update(gfx)
+    }
+
+    pub fn render(&mut self, gfx: &[u8], size: (usize, usize)) -> &Image
+    {
+        if self.frame.width != size.0 || self.frame.height != size.1
+        {
+            self.frame = Image::new(size.0, size.1);
+        }
+
+        let pitch = self.frame.pitch();
+        self.phosphor.render(gfx, size.0, self.palettes.current(), &mut self.frame.data, pitch);
+
+        let image = if self.filters.is_empty()
+        {
+            &self.frame
+        }
+        else
+        {
+            self.frame.upscale(self.filter_scale, &mut self.scaled);
+            filters::apply(&self.filters, self.filter_scale, &mut self.scaled);
+            &self.scaled
+        };
+
+        if self.rotation == 0
+        {
+            return image;
+        }
+        rotate(image, self.rotation, &mut self.rotated);
+        &self.rotated
+    }
+
+    // size of the displayed picture for a framebuffer of `size`
+    pub fn output_size(&self, size: (usize, usize)) -> (usize, usize)
+    {
+        if self.rotation % 2 == 1 { (size.1, size.0) } else { size }
+    }
+
+    pub fn screenshot(&mut self, gfx: &[u8], size: (usize, usize), filename: &str) -> Result<(), String>
+    {
+        let image = self.render(gfx, size);
+        png::write(filename, image.width, image.height, &image.data).map_err(|e| format!("{}: {}", filename, e))
+    }
+}
+
+// turns the image clockwise by quarter turns
+fn rotate(image: &Image, quarters: u32, out: &mut Image)
+{
+    let (width, height) = if quarters % 2 == 1 { (image.height, image.width) } else { (image.width, image.height) };
+    if out.width != width || out.height != height
+    {
+        *out = Image::new(width, height);
+    }
+
+    for y in 0..image.height
+    {
+        for x in 0..image.width
+        {
+            let (to_x, to_y) = match quarters
+            {
+                1 => (image.height - 1 - y, x),
+                2 => (image.width - 1 - x, image.height - 1 - y),
+                _ => (y, image.width - 1 - x),
+            };
+            let from = (y * image.width + x) * 3;
+            let to = (to_y * width + to_x) * 3;
+            out.data[to..to + 3].copy_from_slice(&image.data[from..from + 3]);
+        }
+    }
+}
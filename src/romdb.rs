@@ -0,0 +1,260 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::fs;
+use std::path::Path;
+
+use crate::config::{self, Config};
+use crate::json::Json;
+
+// metadata of known ROMs, built into the binary
+const BUNDLED: &str = include_str!("../roms.json");
+
+// additional database looked up in the working directory
+pub const USER_DATABASE: &str = "dale8-roms.json";
+
+// quirks of the platforms of the community database, as shift, memory,
+// jump and vf_reset. memoryIncrementByX (CHIP-48) is approximated by the
+// regular increment. waiting for the display is left to the ROM's vblank
+// override and waiting for key releases to the configuration, both being
+// opt-in (see [quirks] in scanner.rs)
+const PLATFORMS: [(&str, &str, [bool; 4]); 9] =
+[
+    ("originalChip8", "chip-8", [false, true, false, true]),
+    ("hybridVIP", "chip-8", [false, true, false, true]),
+    ("modernChip8", "chip-8", [false, true, false, false]),
+    ("chip8x", "chip-8", [false, true, false, true]),
+    ("chip48", "schip", [true, true, true, false]),
+    ("superchip1", "schip", [true, false, true, false]),
+    ("superchip", "schip", [true, false, true, false]),
+    ("megachip8", "schip", [true, false, true, false]),
+    ("xochip", "xo-chip", [false, true, false, false]),
+];
+
+// controller inputs for the key names of the database
+const KEYS: [(&str, &[&str]); 6] =
+[
+    ("up", &["dpup", "lefty-"]),
+    ("down", &["dpdown", "lefty+"]),
+    ("left", &["dpleft", "leftx-"]),
+    ("right", &["dpright", "leftx+"]),
+    ("a", &["a"]),
+    ("b", &["b"]),
+];
+
+// ROM metadata in the format of the community CHIP-8 database
+// (programs.json): a list of programs, each with its ROM files keyed by
+// SHA-1
+//
+//   [
+//     {
+//       "title": "Tetris",
+//       "authors": ["Fran Dachille"],
+//       "roms": {
+//         "5f518084744bf3cb8733f6e5454dfd1634320563": {
+//           "platforms": ["originalChip8"],
+//           "quirkyPlatforms": { "originalChip8": { "shift": true } },
+//           "tickrate": 15,
+//           "colors": { "pixels": ["#000000", "#ffffff"] },
+//           "keys": { "left": 5, "right": 6, "a": 4 }
+//         }
+//       }
+//     }
+//   ]
+pub struct Database
+{
+    programs: Vec<Json>,
+}
+
+// a known ROM and the program it belongs to
+pub struct Entry<'a>
+{
+    program: &'a Json,
+    rom: &'a Json,
+}
+
+impl Database
+{
+    // the bundled database, then dale8-roms.json and the files listed in
+    // [database] files. later files take precedence
+    pub fn load(config: &Config) -> Result<Database, String>
+    {
+        let mut database = Database { programs: Vec::new() };
+        database.add(BUNDLED).map_err(|e| format!("bundled database: {}", e))?;
+
+        let mut files = Vec::new();
+        if Path::new(USER_DATABASE).exists()
+        {
+            files.push(USER_DATABASE);
+        }
+        if let Some(list) = config.get("database", "files")
+        {
+            files.extend(config::list(list));
+        }
+
+        for filename in files
+        {
+            let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+            database.add(&text).map_err(|e| format!("{}: {}", filename, e))?;
+        }
+        Ok(database)
+    }
+
+    pub fn add(&mut self, text: &str) -> Result<(), String>
+    {
+        match Json::parse(text)?
+        {
+            Json::Array(programs) => self.programs.extend(programs),
+            _ => return Err("expected a list of programs".to_string()),
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<Entry<'_>>
+    {
+        self.programs.iter().rev().find_map(|program|
+        {
+            let roms = program.get("roms")?.as_object()?;
+            roms.iter().find(|r| r.0.eq_ignore_ascii_case(sha1)).map(|r| Entry { program, rom: &r.1 })
+        })
+    }
+}
+
+impl<'a> Entry<'a>
+{
+    pub fn title(&self) -> Option<&str>
+    {
+        self.program.get("title").and_then(|t| t.as_str())
+    }
+
+    pub fn authors(&self) -> Vec<&str>
+    {
+        let authors = self.program.get("authors").and_then(|a| a.as_array()).unwrap_or(&[]);
+        authors.iter().filter_map(|a| a.as_str()).collect()
+    }
+
+    // the settings of the ROM as configuration entries, to go between the
+    // global and the per-ROM configuration
+    pub fn config(&self) -> Config
+    {
+        let mut config = Config::default();
+
+        if let Some(title) = self.title()
+        {
+            config.set("rom", "title", title.to_string());
+        }
+        if !self.authors().is_empty()
+        {
+            config.set("rom", "authors", self.authors().join(", "));
+        }
+
+        // the first platform listed is the one the ROM was written for
+        let platform = self.rom.get("platforms").and_then(|p| p.as_array()).and_then(|p| p.first()).and_then(|p| p.as_str());
+        if let Some(&(id, name, quirks)) = platform.and_then(|id| PLATFORMS.iter().find(|p| p.0 == id))
+        {
+            let overrides = self.rom.get("quirkyPlatforms").and_then(|q| q.get(id));
+            let quirk = |names: &[&str], invert: bool, default: bool|
+            {
+                names.iter().find_map(|n| overrides.and_then(|o| o.get(n)).and_then(|v| v.as_bool()))
+                    .map_or(default, |v| v != invert)
+            };
+
+            config.set("emulation", "platform", name.to_string());
+            config.set("quirks", "shift", quirk(&["shift"], false, quirks[0]).to_string());
+            config.set("quirks", "memory", quirk(&["memoryLeaveIUnchanged"], true, quirks[1]).to_string());
+            config.set("quirks", "jump", quirk(&["jump"], false, quirks[2]).to_string());
+            config.set("quirks", "vf_reset", quirk(&["logic"], false, quirks[3]).to_string());
+            if let Some(vblank) = overrides.and_then(|o| o.get("vblank")).and_then(|v| v.as_bool())
+            {
+                config.set("quirks", "display_wait", vblank.to_string());
+            }
+        }
+
+        if let Some(tickrate) = self.rom.get("tickrate").and_then(|t| t.as_f64())
+        {
+            config.set("emulation", "ipf", (tickrate as u32).to_string());
+        }
+
+        // background and foreground, or all four colours of two planes
+        let pixels: Vec<&str> = self.rom.get("colors").and_then(|c| c.get("pixels")).and_then(|p| p.as_array())
+            .map_or(Vec::new(), |p| p.iter().filter_map(|c| c.as_str()).collect());
+        if pixels.len() >= 2
+        {
+            let count = if pixels.len() >= 4 { 4 } else { 2 };
+            config.set("display", "palette", pixels[..count].join(","));
+        }
+
+        if let Some(keys) = self.rom.get("keys")
+        {
+            for &(name, inputs) in KEYS.iter()
+            {
+                if let Some(key) = keys.get(name).and_then(|k| k.as_f64())
+                {
+                    for input in inputs
+                    {
+                        config.set("controller", input, format!("{:X}", key as usize));
+                    }
+                }
+            }
+        }
+
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn bundled() -> Database
+    {
+        let mut database = Database { programs: Vec::new() };
+        database.add(BUNDLED).unwrap();
+        database
+    }
+
+    #[test]
+    fn bundled_rom()
+    {
+        let database = bundled();
+        let config = database.lookup("1830EB401BA8789A477DFCF294873A5479EBCFE8").unwrap().config();
+
+        assert_eq!(config.get("rom", "title"), Some("Pong 2"));
+        assert_eq!(config.get("emulation", "platform"), Some("chip-8"));
+        assert_eq!(config.get("emulation", "ipf"), Some("10"));
+        assert_eq!(config.get("quirks", "shift"), Some("false"));
+        assert_eq!(config.get("quirks", "memory"), Some("true"));
+        assert_eq!(config.get("quirks", "vf_reset"), Some("true"));
+        assert_eq!(config.get("controller", "dpup"), Some("1"));
+
+        // originalChip8 doesn't bring the waits of the VIP
+        assert_eq!(config.get("quirks", "display_wait"), None);
+        assert_eq!(config.get("quirks", "key_release"), None);
+    }
+
+    #[test]
+    fn quirk_overrides()
+    {
+        let mut database = bundled();
+        database.add(r#"[{ "title": "Test", "roms": { "0123": {
+            "platforms": ["originalChip8"],
+            "quirkyPlatforms": { "originalChip8": { "shift": true, "memoryLeaveIUnchanged": true, "vblank": true } } } } }]"#).unwrap();
+        let config = database.lookup("0123").unwrap().config();
+
+        assert_eq!(config.get("quirks", "shift"), Some("true"));
+        assert_eq!(config.get("quirks", "memory"), Some("false"));
+        assert_eq!(config.get("quirks", "display_wait"), Some("true"));
+        assert_eq!(config.get("quirks", "key_release"), None);
+    }
+}
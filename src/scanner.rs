@@ -0,0 +1,436 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::Config;
+use crate::dale8::Quirks;
+use crate::options::Options;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Platform
+{
+    Chip8,  // CHIP-8 as on the COSMAC VIP
+    Vip,    // the COSMAC VIP interpreter down to its waits for the display and key releases
+    Schip,  // SUPER-CHIP 1.1 on the HP 48
+    XoChip,
+}
+
+impl Platform
+{
+    pub fn parse(name: &str) -> Result<Platform, String>
+    {
+        match name
+        {
+            "chip-8" | "chip8" => Ok(Platform::Chip8),
+            "vip" => Ok(Platform::Vip),
+            "schip" | "superchip" => Ok(Platform::Schip),
+            "xo-chip" | "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Platform::Chip8 => "chip-8",
+            Platform::Vip => "vip",
+            Platform::Schip => "schip",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+
+    pub fn quirks(self) -> Quirks
+    {
+        match self
+        {
+            // waiting for the display or a key release is opt-in, see
+            // [quirks] display_wait and key_release
+            Platform::Chip8 => Quirks { shift: false, memory: true, jump: false, vf_reset: true, display_wait: false, key_release: false },
+            Platform::Vip => Quirks { shift: false, memory: true, jump: false, vf_reset: true, display_wait: true, key_release: true },
+            Platform::Schip => Quirks { shift: true, memory: false, jump: true, vf_reset: false, display_wait: false, key_release: false },
+            Platform::XoChip => Quirks { shift: false, memory: true, jump: false, vf_reset: false, display_wait: false, key_release: false },
+        }
+    }
+}
+
+// what a ROM uses, found by following its code from the entry point
+pub struct Scan
+{
+    pub histogram: BTreeMap<&'static str, u32>,
+    pub schip: BTreeSet<&'static str>,
+    pub xo_chip: BTreeSet<&'static str>,
+    pub machine_calls: Vec<u16>,       // 0NNN
+    pub shifts: Vec<u16>,              // 8XY6/8XYE with X != Y
+    pub jumps: Vec<u16>,               // BXNN with X != 0
+    pub instructions: usize,
+}
+
+impl Scan
+{
+    // the platform the instructions point to. programs without SUPER-CHIP or
+    // XO-CHIP instructions are plain CHIP-8, see quirks for the rest
+    pub fn platform(&self) -> Platform
+    {
+        if !self.xo_chip.is_empty()
+        {
+            Platform::XoChip
+        }
+        else if !self.schip.is_empty()
+        {
+            Platform::Schip
+        }
+        else
+        {
+            Platform::Chip8
+        }
+    }
+
+    // the quirks of the platform, with the shift and jump quirks turned on
+    // when the code has the instructions that only make sense with them.
+    // CHIP-48 games shift in place and are otherwise plain CHIP-8
+    pub fn quirks(&self) -> Quirks
+    {
+        let mut quirks = self.platform().quirks();
+        quirks.shift |= !self.shifts.is_empty();
+        quirks.jump |= !self.jumps.is_empty();
+        quirks
+    }
+
+    pub fn report(&self) -> String
+    {
+        let mut report = format!("{} reachable instructions\n\n", self.instructions);
+
+        let mut histogram: Vec<(&&str, &u32)> = self.histogram.iter().collect();
+        histogram.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (class, count) in histogram
+        {
+            report += &format!("  {:<6} {:>5}\n", class, count);
+        }
+        report += "\n";
+
+        if !self.schip.is_empty()
+        {
+            report += &format!("SUPER-CHIP instructions: {}\n", list(&self.schip));
+        }
+        if !self.xo_chip.is_empty()
+        {
+            report += &format!("XO-CHIP instructions: {}\n", list(&self.xo_chip));
+        }
+        if !self.machine_calls.is_empty()
+        {
+            report += &format!("machine code calls (0NNN) at {}\n", addresses(&self.machine_calls));
+        }
+        if !self.shifts.is_empty()
+        {
+            report += &format!("shifts with X != Y, depend on the shift quirk, at {}\n", addresses(&self.shifts));
+        }
+        if !self.jumps.is_empty()
+        {
+            report += &format!("BXNN jumps with X != 0, depend on the jump quirk, at {}\n", addresses(&self.jumps));
+        }
+
+        let quirks = self.quirks();
+        report += &format!("\nrecommended platform: {}\n", self.platform().name());
+        report += &format!("  shift = {}\n  memory = {}\n  jump = {}\n  vf_reset = {}\n  display_wait = {}\n  key_release = {}\n",
+            quirks.shift, quirks.memory, quirks.jump, quirks.vf_reset, quirks.display_wait, quirks.key_release);
+        report
+    }
+}
+
+fn list(names: &BTreeSet<&str>) -> String
+{
+    names.iter().cloned().collect::<Vec<&str>>().join(", ")
+}
+
+fn addresses(addresses: &[u16]) -> String
+{
+    addresses.iter().map(|a| format!("{:03X}", a)).collect::<Vec<String>>().join(", ")
+}
+
+// instruction pattern of an opcode, and the extension that introduced it
+fn classify(opcode: u16) -> (&'static str, Option<Platform>)
+{
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+
+    match opcode & 0xF000
+    {
+        0x0000 => match opcode
+        {
+            0x00E0 => ("00E0", None),
+            0x00EE => ("00EE", None),
+            0x00FB => ("00FB", Some(Platform::Schip)),
+            0x00FC => ("00FC", Some(Platform::Schip)),
+            0x00FD => ("00FD", Some(Platform::Schip)),
+            0x00FE => ("00FE", Some(Platform::Schip)),
+            0x00FF => ("00FF", Some(Platform::Schip)),
+            _ if opcode & 0xFFF0 == 0x00C0 => ("00CN", Some(Platform::Schip)),
+            _ if opcode & 0xFFF0 == 0x00D0 => ("00DN", Some(Platform::XoChip)),
+            _ => ("0NNN", None),
+        },
+        0x1000 => ("1NNN", None),
+        0x2000 => ("2NNN", None),
+        0x3000 => ("3XNN", None),
+        0x4000 => ("4XNN", None),
+        0x5000 => match n
+        {
+            0x0 => ("5XY0", None),
+            0x2 => ("5XY2", Some(Platform::XoChip)),
+            0x3 => ("5XY3", Some(Platform::XoChip)),
+            _ => ("data", None),
+        },
+        0x6000 => ("6XNN", None),
+        0x7000 => ("7XNN", None),
+        0x8000 => match n
+        {
+            0x0 => ("8XY0", None),
+            0x1 => ("8XY1", None),
+            0x2 => ("8XY2", None),
+            0x3 => ("8XY3", None),
+            0x4 => ("8XY4", None),
+            0x5 => ("8XY5", None),
+            0x6 => ("8XY6", None),
+            0x7 => ("8XY7", None),
+            0xE => ("8XYE", None),
+            _ => ("data", None),
+        },
+        0x9000 if n == 0 => ("9XY0", None),
+        0xA000 => ("ANNN", None),
+        0xB000 => ("BNNN", None),
+        0xC000 => ("CXNN", None),
+        0xD000 if n == 0 => ("DXY0", Some(Platform::Schip)),
+        0xD000 => ("DXYN", None),
+        0xE000 => match nn
+        {
+            0x9E => ("EX9E", None),
+            0xA1 => ("EXA1", None),
+            _ => ("data", None),
+        },
+        0xF000 => match nn
+        {
+            0x00 if opcode == 0xF000 => ("F000", Some(Platform::XoChip)),
+            0x01 => ("FN01", Some(Platform::XoChip)),
+            0x02 if opcode == 0xF002 => ("F002", Some(Platform::XoChip)),
+            0x07 => ("FX07", None),
+            0x0A => ("FX0A", None),
+            0x15 => ("FX15", None),
+            0x18 => ("FX18", None),
+            0x1E => ("FX1E", None),
+            0x29 => ("FX29", None),
+            0x30 => ("FX30", Some(Platform::Schip)),
+            0x33 => ("FX33", None),
+            0x3A => ("FX3A", Some(Platform::XoChip)),
+            0x55 => ("FX55", None),
+            0x65 => ("FX65", None),
+            // SUPER-CHIP only has 8 flag registers
+            0x75 | 0x85 if opcode & 0x0F00 > 0x0700 => (if nn == 0x75 { "FX75" } else { "FX85" }, Some(Platform::XoChip)),
+            0x75 => ("FX75", Some(Platform::Schip)),
+            0x85 => ("FX85", Some(Platform::Schip)),
+            _ => ("data", None),
+        },
+        _ => ("data", None),
+    }
+}
+
+// follows the code from `entry`, through both sides of every skip and into
+// every subroutine. computed jumps (BNNN) can't be followed, code only
+// reached through them is not scanned
+pub fn scan(memory: &[u8], entry: u16, end: u16) -> Scan
+{
+    let mut result = Scan
+    {
+        histogram: BTreeMap::new(),
+        schip: BTreeSet::new(),
+        xo_chip: BTreeSet::new(),
+        machine_calls: Vec::new(),
+        shifts: Vec::new(),
+        jumps: Vec::new(),
+        instructions: 0,
+    };
+
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![entry];
+
+    while let Some(address) = pending.pop()
+    {
+        if address < entry || address + 1 >= end || !visited.insert(address)
+        {
+            continue;
+        }
+
+        let opcode = (memory[address as usize] as u16) << 8 | memory[address as usize + 1] as u16;
+        let (class, platform) = classify(opcode);
+        if class == "data"
+        {
+            continue;
+        }
+
+        result.instructions += 1;
+        *result.histogram.entry(class).or_insert(0) += 1;
+        match platform
+        {
+            Some(Platform::Schip) => { result.schip.insert(class); },
+            Some(Platform::XoChip) => { result.xo_chip.insert(class); },
+            _ => (),
+        }
+
+        let x = (opcode & 0x0F00) >> 8;
+        let y = (opcode & 0x00F0) >> 4;
+        match class
+        {
+            "0NNN" => result.machine_calls.push(address),
+            "8XY6" | "8XYE" if x != y => result.shifts.push(address),
+            "BNNN" if x != 0 => result.jumps.push(address),
+            _ => (),
+        }
+
+        // XO-CHIP's F000 NNNN is four bytes long, skips jump over all of it
+        let next = if class == "F000" { address + 4 } else { address + 2 };
+        match class
+        {
+            "1NNN" => pending.push(opcode & 0x0FFF),
+            "2NNN" =>
+            {
+                pending.push(opcode & 0x0FFF);
+                pending.push(next);
+            },
+            "00EE" | "00FD" | "BNNN" => (),
+            "3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1" | "5XY2" | "5XY3" =>
+            {
+                let skipped = (memory[next as usize % memory.len()] as u16) << 8 | memory[(next as usize + 1) % memory.len()] as u16;
+                pending.push(next);
+                pending.push(next + if skipped == 0xF000 { 4 } else { 2 });
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    result.machine_calls.sort_unstable();
+    result.shifts.sort_unstable();
+    result.jumps.sort_unstable();
+    result
+}
+
+// the quirks to run with:
+//
+//   [emulation]
+//   platform = auto     # chip-8, vip, schip, xo-chip or auto to scan the ROM
+//
+// auto takes the quirks the scan recommends (see Scan::quirks). without a
+// platform the quirks are the ones this VM always had, and no platform is
+// returned
+//
+//   [quirks]            # on top of the platform
+//   shift = true
+//   memory = true
+//   jump = false
+//   vf_reset = false
+//   display_wait = false
+//   key_release = false
+//
+// VIP timing (see [emulation] timing) makes every draw wait for the display
+// interrupt whatever display_wait says, the interpreter it models does
+pub fn quirks(config: &Config, options: &Options, scan: &Scan) -> Result<(Option<Platform>, Quirks), String>
+{
+    let name = match options.platform
+    {
+        Some(ref name) => Some(name.clone()),
+        None => config.value("emulation", "platform")?,
+    };
+
+    let platform = match name.as_deref()
+    {
+        Some("auto") => Some((scan.platform(), scan.quirks())),
+        Some(name) =>
+        {
+            let platform = Platform::parse(name)?;
+            Some((platform, platform.quirks()))
+        },
+        None => None,
+    };
+    let mut quirks = platform.map_or_else(Quirks::default, |(_, quirks)| quirks);
+
+    if let Some(shift) = config.value("quirks", "shift")? { quirks.shift = shift; }
+    if let Some(memory) = config.value("quirks", "memory")? { quirks.memory = memory; }
+    if let Some(jump) = config.value("quirks", "jump")? { quirks.jump = jump; }
+    if let Some(vf_reset) = config.value("quirks", "vf_reset")? { quirks.vf_reset = vf_reset; }
+    if let Some(display_wait) = config.value("quirks", "display_wait")? { quirks.display_wait = display_wait; }
+    if let Some(key_release) = config.value("quirks", "key_release")? { quirks.key_release = key_release; }
+
+    Ok((platform.map(|(platform, _)| platform), quirks))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // a program at 0x200 followed by a jump to itself
+    fn rom(code: &[u16]) -> (Vec<u8>, u16)
+    {
+        let mut memory = vec![0; 0x1000];
+        let mut address = 0x200;
+        for opcode in code.iter().chain(&[0x1000 | (0x200 + 2 * code.len() as u16)])
+        {
+            memory[address] = (opcode >> 8) as u8;
+            memory[address + 1] = *opcode as u8;
+            address += 2;
+        }
+        (memory, address as u16)
+    }
+
+    #[test]
+    fn shift_in_place()
+    {
+        // v1 >>= 1 and v3 <<= 1 ignoring vY, as invaders does
+        let (memory, end) = rom(&[0x6105, 0x8126, 0x823E]);
+        let scan = scan(&memory, 0x200, end);
+        assert_eq!(scan.shifts, [0x202, 0x204]);
+        assert_eq!(scan.platform(), Platform::Chip8);
+        assert!(scan.quirks().shift);
+        assert!(!scan.quirks().jump);
+
+        let options = crate::options::parse(&["dale8".to_string(), "--platform".to_string(), "auto".to_string(), "rom".to_string()]).unwrap();
+        let (platform, quirks) = quirks(&Config::default(), &options, &scan).unwrap();
+        assert_eq!(platform, Some(Platform::Chip8));
+        assert!(quirks.shift && quirks.memory && !quirks.display_wait);
+
+        // an explicit setting still wins
+        let config = Config::parse("[quirks]\nshift = false").unwrap();
+        assert!(!super::quirks(&config, &options, &scan).unwrap().1.shift);
+    }
+
+    #[test]
+    fn shifts_of_one_register()
+    {
+        // the same under either quirk
+        let (memory, end) = rom(&[0x8336, 0x800E]);
+        let scan = scan(&memory, 0x200, end);
+        assert!(scan.shifts.is_empty());
+        assert!(!scan.quirks().shift);
+    }
+
+    #[test]
+    fn jumps()
+    {
+        // B0NN adds V0 under either quirk
+        let (memory, end) = rom(&[0xB050]);
+        assert!(!scan(&memory, 0x200, end).quirks().jump);
+        let (memory, end) = rom(&[0xB310]);
+        let scan = scan(&memory, 0x200, end);
+        assert_eq!(scan.jumps, [0x200]);
+        assert!(scan.quirks().jump);
+    }
+}
@@ -0,0 +1,320 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::dale8::{self, VM};
+use crate::options::Options;
+use crate::timing::{Timing, VIP_CYCLES_PER_FRAME};
+
+// instructions per frame when not configured, about 600 per second
+pub const DEFAULT_IPF: u32 = 10;
+
+// fractions of the configured speed cycled through by the slow motion key
+const SLOW_MOTION: [f64; 3] = [1.0, 0.5, 0.25];
+
+// runs the VM in 60 Hz frames: a fixed number of instructions followed by a
+// timer tick, then sleeps until the next frame is due.
+//
+// the host always presents at 60 Hz (or at the display refresh with vsync),
+// the speed decides how many emulated frames fit into each host frame
+pub struct Scheduler
+{
+    pub instructions_per_frame: u32,
+    pub vsync: bool,
+
+    pub speed: f64,
+    base_speed: f64, // the configured speed, slow motion is a fraction of it
+    slow_motion: usize,
+    pub paused: bool,
+    step: bool,
+
+    // speed while fast-forwarding, None runs as fast as possible
+    pub fast_forward: bool,
+    pub fast_forward_speed: Option<f64>,
+
+    budget: f64, // emulated frames owed to the current speed
+    frame_duration: Duration,
+    deadline: Option<Instant>, // set by the first frame
+    last_frame: Instant,
+
+    cycles: u32, // machine cycles already spent on the next frame
+
+    pub frames: u64,
+    pub emulated_frames: u64,
+    pub instructions: u64,
+    pub dropped_frames: u64,
+}
+
+impl Scheduler
+{
+    // with vsync the presentation of the frame already blocks, so the
+    // scheduler only keeps track of frames that came too late.
+    //
+    //   [emulation]
+    //   ipf = 10
+    //   speed = 1
+    //   fast_forward = 4    # 0 runs as fast as possible
+    pub fn new(config: &Config, options: &Options) -> Result<Scheduler, String>
+    {
+        let instructions_per_frame = match options.ipf
+        {
+            Some(ipf) => ipf,
+            None => config.value("emulation", "ipf")?.unwrap_or(DEFAULT_IPF),
+        };
+
+        let speed = match options.speed
+        {
+            Some(speed) => speed,
+            None => config.value("emulation", "speed")?.unwrap_or(1.0),
+        };
+        if !(speed.is_finite() && speed > 0.0)
+        {
+            return Err(format!("invalid speed {}", speed));
+        }
+
+        let fast_forward_speed = match config.value::<f64>("emulation", "fast_forward")?
+        {
+            Some(speed) if speed.is_finite() && speed > 0.0 => Some(speed),
+            _ => None,
+        };
+
+        Ok(Scheduler
+        {
+            instructions_per_frame,
+            vsync: options.vsync || config.value("display", "vsync")?.unwrap_or(false),
+
+            speed,
+            base_speed: speed,
+            slow_motion: 0,
+            paused: false,
+            step: false,
+
+            fast_forward: false,
+            fast_forward_speed,
+
+            budget: 0.0,
+            frame_duration: Duration::from_nanos(1_000_000_000 / dale8::TIMER_FREQUENCY as u64),
+            deadline: None,
+            last_frame: Instant::now(),
+
+            cycles: 0,
+
+            frames: 0,
+            emulated_frames: 0,
+            instructions: 0,
+            dropped_frames: 0,
+        })
+    }
+
+    pub fn toggle_pause(&mut self)
+    {
+        self.paused = !self.paused;
+        self.budget = 0.0;
+    }
+
+    // runs exactly one frame on the next call to run, pausing if needed
+    pub fn advance_frame(&mut self)
+    {
+        self.paused = true;
+        self.step = true;
+    }
+
+    // 1x -> 1/2x -> 1/4x -> 1x of the configured speed
+    pub fn cycle_slow_motion(&mut self) -> f64
+    {
+        self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION.len();
+        self.speed = self.base_speed * SLOW_MOTION[self.slow_motion];
+        self.speed
+    }
+
+    // runs the emulated frames due in this host frame, calling on_frame
+    // after each of them
+    pub fn run<F: FnMut(&mut VM)>(&mut self, vm: &mut VM, mut on_frame: F)
+    {
+        if self.paused
+        {
+            if self.step
+            {
+                self.step = false;
+                self.run_frame(vm);
+                on_frame(vm);
+            }
+            return;
+        }
+
+        let speed = match (self.fast_forward, self.fast_forward_speed)
+        {
+            (true, Some(speed)) => speed,
+
+            // uncapped: keep going for the duration of one host frame
+            (true, None) =>
+            {
+                let start = Instant::now();
+                while start.elapsed() < self.frame_duration
+                {
+                    self.run_frame(vm);
+                    on_frame(vm);
+                }
+                return;
+            },
+
+            (false, _) => self.speed,
+        };
+
+        self.budget += speed;
+        while self.budget >= 1.0
+        {
+            self.budget -= 1.0;
+            self.run_frame(vm);
+            on_frame(vm);
+        }
+    }
+
+    pub fn run_frame(&mut self, vm: &mut VM)
+    {
+        match vm.timing
+        {
+            Timing::Uniform =>
+            {
+                for _ in 0..self.instructions_per_frame
+                {
+                    if vm.waiting_for_vblank()
+                    {
+                        break;
+                    }
+                    vm.emulate_cycle();
+                    self.instructions += 1;
+                }
+            },
+
+            // as many instructions as the cycles of a frame allow. a draw
+            // waits for the display interrupt whatever the quirks, giving up
+            // the rest of the frame
+            Timing::Vip =>
+            {
+                while self.cycles < VIP_CYCLES_PER_FRAME && !vm.waiting_for_vblank()
+                {
+                    vm.emulate_cycle();
+                    self.instructions += 1;
+                    self.cycles += vm.cycles();
+                }
+                self.cycles = self.cycles.saturating_sub(VIP_CYCLES_PER_FRAME);
+            },
+        }
+        vm.vblank();
+        vm.update_timers();
+        self.emulated_frames += 1;
+    }
+
+    // call once the frame has been presented
+    pub fn end_frame(&mut self)
+    {
+        self.frames += 1;
+        let now = Instant::now();
+
+        // running uncapped already took a whole frame
+        if self.fast_forward && self.fast_forward_speed.is_none()
+        {
+            self.deadline = None;
+            self.last_frame = now;
+            return;
+        }
+
+        if self.vsync
+        {
+            // allow some jitter before calling a frame lost
+            let elapsed = now.duration_since(self.last_frame);
+            if self.frames > 1 && elapsed > self.frame_duration * 3 / 2
+            {
+                self.dropped_frames += (elapsed.as_nanos() / self.frame_duration.as_nanos()) as u64 - 1;
+            }
+            self.last_frame = now;
+            return;
+        }
+
+        let deadline = self.deadline.unwrap_or(now) + self.frame_duration;
+        self.deadline = Some(deadline);
+
+        if now < deadline
+        {
+            thread::sleep(deadline - now);
+        }
+        else if now - deadline > self.frame_duration
+        {
+            // too far behind to catch up, skip the missed frames
+            self.dropped_frames += ((now - deadline).as_nanos() / self.frame_duration.as_nanos()) as u64;
+            self.deadline = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::options;
+
+    fn scheduler(args: &[&str], config: &str) -> Result<Scheduler, String>
+    {
+        let args: Vec<String> = ["dale8"].iter().chain(args).chain(&["rom"]).map(|a| a.to_string()).collect();
+        Scheduler::new(&Config::parse(config)?, &options::parse(&args)?)
+    }
+
+    #[test]
+    fn invalid_speeds()
+    {
+        for speed in &["0", "-1", "inf", "NaN"]
+        {
+            assert!(scheduler(&["--speed", speed], "").is_err());
+            assert!(scheduler(&[], &format!("[emulation]\nspeed = {}", speed)).is_err());
+        }
+
+        // anything but a finite positive speed runs as fast as possible
+        for speed in &["0", "inf", "NaN"]
+        {
+            let scheduler = scheduler(&[], &format!("[emulation]\nfast_forward = {}", speed)).unwrap();
+            assert_eq!(scheduler.fast_forward_speed, None);
+        }
+    }
+
+    #[test]
+    fn slow_motion_keeps_the_speed()
+    {
+        let mut scheduler = scheduler(&["--speed", "2"], "").unwrap();
+        assert_eq!(scheduler.cycle_slow_motion(), 1.0);
+        assert_eq!(scheduler.cycle_slow_motion(), 0.5);
+        assert_eq!(scheduler.cycle_slow_motion(), 2.0);
+    }
+
+    #[test]
+    fn one_draw_per_frame()
+    {
+        // v0 counts the sprites drawn
+        let mut vm = VM::new();
+        vm.load_rom_bytes(&[0xD0, 0x11, 0x70, 0x01, 0x12, 0x00]).unwrap();
+        vm.quirks.display_wait = true;
+
+        let mut scheduler = scheduler(&["--ipf", "100"], "").unwrap();
+        for _ in 0..5
+        {
+            scheduler.run_frame(&mut vm);
+        }
+
+        // the first frame only gets to the wait, the others draw and wait
+        assert_eq!(vm.v()[0], 4);
+        assert_eq!(scheduler.instructions, 1 + 4 * 4);
+    }
+}
@@ -0,0 +1,154 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use crate::config::Config;
+use crate::options::Options;
+
+// how long instructions take
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Timing
+{
+    // a fixed number of instructions per frame, see [emulation] ipf
+    Uniform,
+
+    // the machine cycles of the COSMAC VIP interpreter, a frame being the
+    // cycles the CPU gets between two display interrupts
+    Vip,
+}
+
+// a VIP frame is 3668 machine cycles, of which the display DMA takes 1024.
+// the interrupt routine's few dozen cycles are left out
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668 - 1024;
+
+// fetching and dispatching an instruction
+const FETCH: u32 = 68;
+
+// extra cycles of a skip that is taken
+pub const VIP_SKIP: u32 = 4;
+
+impl Timing
+{
+    pub fn parse(name: &str) -> Result<Timing, String>
+    {
+        match name
+        {
+            "uniform" => Ok(Timing::Uniform),
+            "vip" => Ok(Timing::Vip),
+            _ => Err(format!("unknown timing {}, expected uniform or vip", name)),
+        }
+    }
+}
+
+// the timing from --timing or [emulation] timing
+pub fn timing(config: &Config, options: &Options) -> Result<Timing, String>
+{
+    match options.timing.as_deref().or_else(|| config.get("emulation", "timing"))
+    {
+        Some(name) => Timing::parse(name),
+        None => Ok(Timing::Uniform),
+    }
+}
+
+// machine cycles of an instruction on the VIP interpreter, without the skip
+// (see VIP_SKIP). `v` and `ir` are the registers before it runs.
+//
+// these are estimates from the length of the interpreter's routines: drawing
+// and the BCD conversion depend on the data, the memory transfers on the
+// number of registers
+pub fn vip_cycles(opcode: u16, v: &[u8; 16], ir: u16) -> u32
+{
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let n = (opcode & 0x000F) as u32;
+
+    FETCH + match opcode & 0xF000
+    {
+        0x0000 => match opcode
+        {
+            0x00E0 => 24 + 3078,
+            0x00EE => 10,
+            _ => 0, // the machine code routine counts for itself
+        },
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => 10,
+        0x5000 | 0x9000 => 14,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xA000 => 12,
+
+        // crossing a page costs the carry
+        0xB000 => if (v[0] as u16 + (opcode & 0xFF)) > 0xFF { 24 } else { 22 },
+        0xC000 => 36,
+
+        // every row is shifted into place and written to one byte of the
+        // display, or two when the sprite isn't byte aligned
+        0xD000 =>
+        {
+            let aligned = v[x] & 7 == 0;
+            let row = if aligned { 34 } else { 34 + 2 * (v[x] as u32 % 8) + 22 };
+            26 + n * row
+        },
+
+        0xE000 => 18,
+        0xF000 => match opcode & 0x00FF
+        {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 18,
+            0x1E => if (ir & 0xFF) + v[x] as u16 > 0xFF { 22 } else { 16 },
+            0x29 => 16,
+
+            // digits are found by repeated subtraction
+            0x33 =>
+            {
+                let value = v[x] as u32;
+                80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+            },
+            0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+            _ => 10,
+        },
+        _ => 10,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn draw_cycles()
+    {
+        // an aligned sprite writes each row to one byte of the display
+        let mut v = [0; 16];
+        assert_eq!(vip_cycles(0xD015, &v, 0), 264);
+
+        // an unaligned one shifts each row and writes two bytes
+        v[0] = 3;
+        assert_eq!(vip_cycles(0xD015, &v, 0), 404);
+        v[0] = 8;
+        assert_eq!(vip_cycles(0xD015, &v, 0), 264);
+    }
+
+    #[test]
+    fn data_dependent_cycles()
+    {
+        let mut v = [0; 16];
+        v[1] = 255;
+        assert_eq!(vip_cycles(0xF133, &v, 0), FETCH + 80 + 16 * (2 + 5 + 5));
+        assert_eq!(vip_cycles(0xF355, &v, 0), FETCH + 14 + 14 * 4);
+        assert_eq!(vip_cycles(0xB0FF, &v, 0), FETCH + 22);
+        v[0] = 1;
+        assert_eq!(vip_cycles(0xB0FF, &v, 0), FETCH + 24);
+    }
+}
@@ -0,0 +1,70 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use sdl2::rect::Rect;
+
+// how the framebuffer is stretched over the window
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scaling
+{
+    Integer, // largest whole multiple that fits, so every pixel has the same size
+    Fit,     // as large as possible while keeping the aspect ratio
+}
+
+impl Scaling
+{
+    pub fn parse(text: &str) -> Result<Scaling, String>
+    {
+        match text
+        {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!("invalid scaling {}", text)),
+        }
+    }
+}
+
+// area of the window covered by the framebuffer, centered and letterboxed
+pub fn viewport(output: (u32, u32), frame: (u32, u32), scaling: Scaling) -> Rect
+{
+    let (out_w, out_h) = (output.0.max(1), output.1.max(1));
+    let (frame_w, frame_h) = (frame.0.max(1), frame.1.max(1));
+
+    let (w, h) = match scaling
+    {
+        Scaling::Integer =>
+        {
+            // fall back to fitting when the window is smaller than the framebuffer
+            let scale = (out_w / frame_w).min(out_h / frame_h);
+            if scale == 0
+            {
+                return viewport(output, frame, Scaling::Fit);
+            }
+            (frame_w * scale, frame_h * scale)
+        },
+
+        Scaling::Fit =>
+        {
+            if out_w as u64 * frame_h as u64 > out_h as u64 * frame_w as u64
+            {
+                (out_h * frame_w / frame_h, out_h)
+            }
+            else
+            {
+                (out_w, out_w * frame_h / frame_w)
+            }
+        },
+    };
+
+    Rect::new(((out_w - w) / 2) as i32, ((out_h - h) / 2) as i32, w.max(1), h.max(1))
+}
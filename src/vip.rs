@@ -0,0 +1,314 @@
+///////////////////////////////////////////////////////////////////////////////
+// Rust port
+// ¯¯¯¯¯¯¯¯¯
+// Name: dale8
+//
+// Author: Daniel Pistelli
+//
+// License: GNU General Public License (GPL) v2
+// ( http://www.gnu.org/licenses/old-licenses/gpl-2.0.html )
+//
+// Copyright (C) 2019 Daniel Pistelli / ntcore.com
+///////////////////////////////////////////////////////////////////////////////
+
+use std::fs;
+
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::config::Config;
+use crate::dale8::{SCREEN_HEIGHT, SCREEN_WIDTH, VM};
+
+// the CDP1861 draws 262 lines of 14 machine cycles each, 60 times a second.
+// 128 of them show the display, fetched by DMA 8 bytes at the start of each
+// line. the interrupt comes 29 cycles before the first DMA so that the
+// interpreter can point R0 at the display, and EF1 is active during the 4
+// lines before the display and the last 4 lines of it
+const CYCLES_PER_LINE: u32 = 14;
+const LINES: u32 = 262;
+const DISPLAY_START: u32 = 80;
+const DISPLAY_LINES: u32 = 128;
+const INTERRUPT_LEAD: u32 = 29;
+const DMA_BYTES: usize = 8;
+
+// where the interpreter expects the CHIP-8 program
+const PROGRAM_START: usize = 0x200;
+
+// the 1802 bus of the VIP. RAM repeats up to 0x8000, the monitor ROM above.
+// after a reset U21 also maps the ROM at 0x0000, until the first address
+// with A15 set
+struct Machine
+{
+    ram: Vec<u8>,
+    monitor: Vec<u8>,
+    boot: bool,
+    display: bool, // the 1861 is turned on by INP 1 and off by OUT 1
+    ef1: bool,
+    key: [u8; 16],
+    selected: usize, // keypad key latched by OUT 2, EF3 when pressed
+    q: bool,         // drives the tone generator
+}
+
+impl Bus for Machine
+{
+    fn read(&mut self, address: u16) -> u8
+    {
+        if address & 0x8000 != 0
+        {
+            self.boot = false;
+        }
+
+        if address & 0x8000 != 0 || self.boot
+        {
+            self.monitor[address as usize % self.monitor.len()]
+        }
+        else
+        {
+            self.ram[address as usize % self.ram.len()]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8)
+    {
+        if address & 0x8000 == 0
+        {
+            let size = self.ram.len();
+            self.ram[address as usize % size] = value;
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8
+    {
+        if port == 1
+        {
+            self.display = true;
+        }
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8)
+    {
+        match port
+        {
+            1 => self.display = false,
+            2 => self.selected = (value & 0xF) as usize,
+            _ => {},
+        }
+    }
+
+    fn flag(&mut self, number: u8) -> bool
+    {
+        match number
+        {
+            1 => self.ef1,
+            3 => self.key[self.selected] != 0,
+            _ => false,
+        }
+    }
+
+    fn q(&mut self, on: bool)
+    {
+        self.q = on;
+    }
+}
+
+// the framebuffers of dale8 and of the VIP, frame by frame
+#[derive(Default)]
+pub struct Comparison
+{
+    pub frames: u64,
+    pub differing: u64,
+    pub first: Option<(u64, usize)>, // frame and number of pixels
+}
+
+impl Comparison
+{
+    pub fn compare(&mut self, vm: &[u8], vip: &[u8])
+    {
+        self.frames += 1;
+        let pixels = vm.iter().zip(vip.iter()).filter(|&(a, b)| (*a != 0) != (*b != 0)).count();
+        if pixels > 0
+        {
+            self.differing += 1;
+            if self.first.is_none()
+            {
+                self.first = Some((self.frames, pixels));
+            }
+        }
+    }
+
+    pub fn report(&self) -> String
+    {
+        match self.first
+        {
+            Some((frame, pixels)) => format!("the framebuffers of dale8 and the VIP differed in {} of {} frames, first in frame {} ({} pixels)",
+                self.differing, self.frames, frame, pixels),
+            None => format!("the framebuffers of dale8 and the VIP matched in all {} frames", self.frames),
+        }
+    }
+}
+
+// a COSMAC VIP running the CHIP-8 interpreter: a CDP1802, the CDP1861 video
+// chip and the hex keypad. it runs next to the VM, which gets compared
+// against it
+pub struct Vip
+{
+    cpu: Cdp1802,
+    machine: Machine,
+    gfx: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    overrun: u32, // cycles the last instruction of a frame took from the next
+    pub comparison: Comparison,
+}
+
+impl Vip
+{
+    // the monitor ROM goes to 0x8000, the interpreter to 0x0000 and the
+    // program to 0x0200 of `ram` bytes of RAM
+    pub fn new(monitor: Vec<u8>, interpreter: &[u8], program: &[u8], ram: usize) -> Result<Vip, String>
+    {
+        if monitor.is_empty() || monitor.len() > 0x8000
+        {
+            return Err(format!("invalid monitor ROM size {}", monitor.len()));
+        }
+        if !ram.is_power_of_two() || !(0x800..=0x8000).contains(&ram)
+        {
+            return Err(format!("invalid RAM size {}, expected a power of two from 2048 to 32768", ram));
+        }
+        if interpreter.len() > PROGRAM_START
+        {
+            return Err(format!("the interpreter is {} bytes, more than the {} before the program", interpreter.len(), PROGRAM_START));
+        }
+        if PROGRAM_START + program.len() > ram
+        {
+            return Err(format!("ROM too big for {} bytes of RAM", ram));
+        }
+
+        let mut memory = vec![0; ram];
+        memory[..interpreter.len()].copy_from_slice(interpreter);
+        memory[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+
+        Ok(Vip
+        {
+            cpu: Cdp1802::new(),
+            machine: Machine
+            {
+                ram: memory,
+                monitor,
+                boot: true,
+                display: false,
+                ef1: false,
+                key: [0; 16],
+                selected: 0,
+                q: false,
+            },
+            gfx: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            overrun: 0,
+            comparison: Comparison::default(),
+        })
+    }
+
+    // the VIP is copyrighted, its ROM and interpreter have to be dumped:
+    //
+    //   [vip]
+    //   monitor = vip-monitor.bin      # 512 bytes
+    //   interpreter = vip-chip8.bin    # loaded at 0x0000
+    //   ram = 4096
+    pub fn load(config: &Config, program: &[u8]) -> Result<Vip, String>
+    {
+        let read = |name: &str|
+        {
+            let filename = config.get("vip", name).ok_or(format!("the VIP needs its {} image, set [vip] {}", name, name))?;
+            fs::read(filename).map_err(|e| format!("{}: {}", filename, e))
+        };
+
+        let monitor = read("monitor")?;
+        let interpreter = read("interpreter")?;
+        let ram = config.value("vip", "ram")?.unwrap_or(4096);
+        Vip::new(monitor, &interpreter, program, ram)
+    }
+
+    pub fn gfx(&self) -> &[u8]
+    {
+        &self.gfx
+    }
+
+    pub fn sound_active(&self) -> bool
+    {
+        self.machine.q
+    }
+
+    // one 1861 frame with the keys of the VM, then the comparison of the
+    // framebuffers
+    pub fn run_frame(&mut self, vm: &VM)
+    {
+        self.machine.key = vm.key;
+
+        let display = self.machine.display;
+        let interrupt_at = DISPLAY_START * CYCLES_PER_LINE - INTERRUPT_LEAD;
+        let mut interrupted = false;
+        let mut cycle = self.overrun;
+
+        for line in 0..LINES
+        {
+            let row = line.wrapping_sub(DISPLAY_START);
+            self.machine.ef1 = display && ((DISPLAY_START - 4..DISPLAY_START).contains(&line) ||
+                (DISPLAY_LINES - 4..DISPLAY_LINES).contains(&row));
+
+            if display && row < DISPLAY_LINES
+            {
+                // each CHIP-8 row is shown on 4 lines, the first is kept
+                for i in 0..DMA_BYTES
+                {
+                    let byte = self.cpu.dma_out(&mut self.machine);
+                    if row % 4 == 0
+                    {
+                        let start = (row as usize / 4 * DMA_BYTES + i) * 8;
+                        for (bit, pixel) in self.gfx[start..start + 8].iter_mut().enumerate()
+                        {
+                            *pixel = (byte >> (7 - bit)) & 1;
+                        }
+                    }
+                }
+                cycle += DMA_BYTES as u32;
+            }
+
+            let end = (line + 1) * CYCLES_PER_LINE;
+            while cycle < end
+            {
+                // held until the interpreter enables interrupts, or the
+                // display begins
+                if display && !interrupted && cycle >= interrupt_at && line < DISPLAY_START && self.cpu.ie
+                {
+                    interrupted = true;
+                    cycle += self.cpu.interrupt();
+                    continue;
+                }
+                cycle += self.cpu.step(&mut self.machine);
+            }
+        }
+
+        self.overrun = cycle - LINES * CYCLES_PER_LINE;
+        if !display
+        {
+            self.gfx.iter_mut().for_each(|p| *p = 0);
+        }
+
+        self.comparison.compare(&vm.gfx, &self.gfx);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn comparison()
+    {
+        let mut comparison = Comparison::default();
+        comparison.compare(&[0, 1, 1], &[0, 1, 1]);
+        comparison.compare(&[0, 1, 1], &[1, 1, 0]);
+        comparison.compare(&[0, 1, 0], &[0, 0, 0]);
+
+        assert_eq!(comparison.first, Some((2, 2)));
+        assert_eq!(comparison.report(), "the framebuffers of dale8 and the VIP differed in 2 of 3 frames, first in frame 2 (2 pixels)");
+    }
+}